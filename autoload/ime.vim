@@ -6,6 +6,29 @@ function! ime#toggle() abort
   endif
 endfunction
 
+function! ime#switch_engine(name) abort
+  call ime#rpc#switch_engine(a:name)
+endfunction
+
+function! ime#add_phrase(text, ...) abort
+  let code = call('ime#rpc#add_phrase', [a:text] + a:000)
+  echo printf('%s: %s', a:text, code)
+endfunction
+
+function! ime#delete_phrase(text, ...) abort
+  call call('ime#rpc#delete_phrase', [a:text] + a:000)
+endfunction
+
+function! ime#reverse_lookup(text, ...) abort
+  let codes = call('ime#rpc#reverse_lookup', [a:text] + a:000)
+  echo printf('%s: %s', a:text, join(codes, ' '))
+endfunction
+
+function! ime#complete_engines(arglead, cmdline, cursorpos) abort
+  let engines = ime#rpc#list_engines()['engines']
+  return filter(engines, 'v:val =~# "^" . a:arglead')
+endfunction
+
 function! s:enable_ime() abort
   let b:__ime_enable = v:true
 
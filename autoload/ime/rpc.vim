@@ -26,12 +26,42 @@ function! ime#rpc#request(method, ...) abort
   return call('rpcrequest', [s:job_id, a:method] + a:000)
 endfunction
 
+function! ime#rpc#flush() abort
+  if s:job_id != 0
+    call rpcrequest(s:job_id, 'flush')
+  endif
+endfunction
+
 function! ime#rpc#register() abort
-  call ime#rpc#request('register_events')
+  call ime#rpc#request('register_events', bufnr('%'))
 endfunction
 
 function! ime#rpc#unregister() abort
-  call ime#rpc#request('unregister_events')
+  call ime#rpc#request('unregister_events', bufnr('%'))
+endfunction
+
+function! ime#rpc#switch_engine(name) abort
+  if exists('b:__ime_context_id')
+    call s:cancel_context(b:__ime_context_id)
+  endif
+
+  return ime#rpc#request('switch_engine', a:name, bufnr('%'))
+endfunction
+
+function! ime#rpc#list_engines() abort
+  return ime#rpc#request('list_engines', bufnr('%'))
+endfunction
+
+function! ime#rpc#add_phrase(text, ...) abort
+  return call('ime#rpc#request', ['add_phrase', a:text, bufnr('%')] + a:000)
+endfunction
+
+function! ime#rpc#delete_phrase(text, ...) abort
+  return call('ime#rpc#request', ['delete_phrase', a:text, bufnr('%')] + a:000)
+endfunction
+
+function! ime#rpc#reverse_lookup(text, ...) abort
+  return call('ime#rpc#request', ['reverse_lookup', a:text, bufnr('%')] + a:000)
 endfunction
 
 function! ime#rpc#start_context() abort
@@ -41,7 +71,7 @@ function! ime#rpc#start_context() abort
     endif
   endif
 
-  let b:__ime_context_id = ime#rpc#request('start_context')
+  let b:__ime_context_id = ime#rpc#request('start_context', bufnr('%'))
 endfunction
 
 function! ime#rpc#input_char(ch) abort
@@ -49,7 +79,34 @@ function! ime#rpc#input_char(ch) abort
     call ime#rpc#start_context()
   endif
 
-  return ime#rpc#request('input_char', b:__ime_context_id, a:ch, bufnr('%'))
+  let res = ime#rpc#request('input_char', b:__ime_context_id, a:ch, bufnr('%'))
+  if res['done']
+    unlet b:__ime_context_id
+  endif
+
+  return res['text']
+endfunction
+
+function! ime#rpc#feed_tone(key, fallback) abort
+  if exists('b:__ime_context_id')
+    if ime#rpc#request('accepts_tone', b:__ime_context_id, a:key, bufnr('%'))
+      return ime#rpc#input_char(a:key)
+    endif
+  endif
+
+  if a:fallback != ''
+    return eval(a:fallback)
+  endif
+
+  return exists('b:__ime_context_id') ? '' : a:key
+endfunction
+
+function! ime#rpc#feed_separator(key) abort
+  if !exists('b:__ime_context_id')
+    return a:key
+  endif
+
+  return ime#rpc#input_char(a:key)
 endfunction
 
 function! ime#rpc#backspace() abort
@@ -66,12 +123,37 @@ function! ime#rpc#backspace() abort
   return ""
 endfunction
 
+function! ime#rpc#delete() abort
+  if !exists('b:__ime_context_id')
+    call feedkeys("\<Del>", 'n')
+    return ""
+  endif
+
+  let res = ime#rpc#request('delete', b:__ime_context_id, bufnr('%'))
+  if res == "canceled"
+    unlet b:__ime_context_id
+  endif
+
+  return ""
+endfunction
+
+function! ime#rpc#move_cursor(motion) abort
+  if !exists('b:__ime_context_id')
+    let keys = {'left': "\<Left>", 'right': "\<Right>", 'start': "\<Home>", 'end': "\<End>"}
+    call feedkeys(keys[a:motion], 'n')
+    return ""
+  endif
+
+  call ime#rpc#request('move_cursor', b:__ime_context_id, a:motion, bufnr('%'))
+  return ""
+endfunction
+
 function! ime#rpc#next_page() abort
   if !exists('b:__ime_context_id')
     return '.'
   endif
 
-  call ime#rpc#request('next_page', bufnr('%'))
+  call ime#rpc#request('next_page', b:__ime_context_id, bufnr('%'))
   return ""
 endfunction
 
@@ -80,7 +162,7 @@ function! ime#rpc#previous_page() abort
     return ','
   endif
 
-  call ime#rpc#request('previous_page', bufnr('%'))
+  call ime#rpc#request('previous_page', b:__ime_context_id, bufnr('%'))
   return ""
 endfunction
 
@@ -106,10 +188,12 @@ function! ime#rpc#confirm(idx) abort
     return ''
   endif
 
-  let txt = ime#rpc#request('confirm', b:__ime_context_id, a:idx, bufnr('%'))
+  let res = ime#rpc#request('confirm', b:__ime_context_id, a:idx, bufnr('%'))
+  if res['done']
+    unlet b:__ime_context_id
+  endif
 
-  call ime#rpc#cancel()
-  return txt
+  return res['text']
 endfunction
 
 function! ime#rpc#cancel() abort
@@ -1,2 +1,10 @@
 inoremap <silent> <C-I><C-I> <C-o>:call ime#toggle()<CR>
+command! -nargs=1 -complete=customlist,ime#complete_engines ImeSwitchEngine call ime#switch_engine(<q-args>)
+command! -nargs=+ ImeAddPhrase call ime#add_phrase(<f-args>)
+command! -nargs=+ ImeDeletePhrase call ime#delete_phrase(<f-args>)
+command! -nargs=+ ImeReverseLookup call ime#reverse_lookup(<f-args>)
 
+augroup ime_flush
+  autocmd!
+  autocmd VimLeavePre * call ime#rpc#flush()
+augroup END
@@ -1,6 +1,5 @@
 use async_std;
-use async_std::sync::Mutex;
-use ime::engine::codetable::code_table::CodeTable;
+use ime::engine::Profiles;
 use ime::path::LocalDataPath;
 use ime::plugin::PluginManager;
 use log::{error, info, LevelFilter, SetLoggerError};
@@ -11,7 +10,7 @@ use log4rs::{
   encode::pattern::PatternEncoder,
 };
 use nvim_rs::create::async_std as create;
-use std::sync::Arc;
+use std::collections::HashMap;
 
 #[async_std::main]
 async fn main() -> Result<(), SetLoggerError> {
@@ -45,11 +44,33 @@ async fn main() -> Result<(), SetLoggerError> {
 
   info!("ime-neovim start...");
 
-  let handler = PluginManager::new(Arc::new(Mutex::new(CodeTable::table_file("小鹤音形.txt"))));
+  let (default_engine, reverse_lookup, engines, errors) = match Profiles::new("config.json") {
+    Ok(profiles) => {
+      let default_engine = profiles.default.clone();
+      let reverse_lookup = profiles.reverse_lookup.clone();
+      let (engines, errors) = profiles.engines();
+      (default_engine, reverse_lookup, engines, errors)
+    }
+    Err(err) => (String::new(), HashMap::new(), HashMap::new(), vec![err]),
+  };
+  for err in &errors {
+    error!("{}", err);
+  }
+
+  let handler = PluginManager::new(default_engine, engines, reverse_lookup);
+  // shares the engines with the handler, to flush them once it stops
+  let manager = handler.clone();
 
   info!("init PluginManager success");
   let (nvim, io_handler) = create::new_parent(handler).await;
 
+  for err in errors {
+    nvim
+      .err_writeln(&format!("ime-neovim: {}", err))
+      .await
+      .unwrap_or_else(|e| error!("failed to report error: '{}'", e));
+  }
+
   match io_handler.await {
     Err(err) => {
       if !err.is_reader_error() {
@@ -83,6 +104,7 @@ async fn main() -> Result<(), SetLoggerError> {
     }
     Ok(()) => {}
   }
+  manager.flush_engines().await;
 
   Ok(())
 }
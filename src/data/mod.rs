@@ -1,4 +1,4 @@
 pub mod persistent_trie;
 mod trie;
 
-pub use persistent_trie::{PersistentNode, PersistentTrie};
+pub use persistent_trie::{PersistentNode, PersistentTrie, TrieBuilder};
@@ -1,12 +1,10 @@
-use log::info;
 use std::collections::{HashMap, LinkedList};
-use std::fmt::Debug;
 use std::hash::Hash;
-use std::sync::{Arc, Mutex, Weak};
+use std::iter::FromIterator;
+use std::sync::Arc;
 
 #[derive(Debug)]
 pub struct PersistentNode<K: Hash + Eq + Clone, V> {
-  pub father: Option<Weak<Self>>,
   pub children: HashMap<K, Arc<Self>>,
   pub values: Vec<Arc<V>>,
 }
@@ -14,7 +12,6 @@ pub struct PersistentNode<K: Hash + Eq + Clone, V> {
 impl<K: Hash + Eq + Clone, V> Clone for PersistentNode<K, V> {
   fn clone(&self) -> Self {
     Self {
-      father: self.father.clone(),
       children: self.children.clone(),
       values: self.values.clone(),
     }
@@ -24,7 +21,6 @@ impl<K: Hash + Eq + Clone, V> Clone for PersistentNode<K, V> {
 impl<K: Hash + Eq + Clone, V> PersistentNode<K, V> {
   pub fn new() -> Self {
     PersistentNode {
-      father: None,
       children: HashMap::new(),
       values: Vec::new(),
     }
@@ -73,19 +69,6 @@ impl<K: Hash + Eq + Clone, V> PersistentNode<K, V> {
   }
 }
 
-impl<K: Hash + Eq + Clone + Debug, V: Debug> PersistentNode<K, V> {
-  pub fn maintain(self: Arc<Self>, father: Option<Weak<Self>>) {
-    unsafe {
-      let mut tmp = self.clone();
-      (*Arc::get_mut_unchecked(&mut tmp)).father = father;
-    }
-
-    for (_, child) in &self.children {
-      child.clone().maintain(Some(Arc::downgrade(&self)));
-    }
-  }
-}
-
 impl<'a, K: Hash + Eq + Clone + 'a, V> PersistentNode<K, V> {
   pub fn update<I>(&self, pattern: &mut I, value: V) -> Arc<Self>
   where
@@ -112,6 +95,30 @@ impl<'a, K: Hash + Eq + Clone + 'a, V> PersistentNode<K, V> {
   }
 }
 
+impl<'a, K: Hash + Eq + Clone + 'a, V> PersistentNode<K, V> {
+  pub fn remove<I, F>(&self, pattern: &mut I, f: &F) -> Option<Arc<Self>>
+  where
+    I: Iterator<Item = &'a K>,
+    F: Fn(&V) -> bool,
+  {
+    let mut this = self.clone();
+
+    if let Some(ch) = pattern.next() {
+      let child = this.children.get(ch)?.remove(pattern, f)?;
+      this.children.insert(ch.clone(), child);
+    } else {
+      let len = this.values.len();
+      this.values.retain(|v| !f(v));
+
+      if this.values.len() == len {
+        return None;
+      }
+    }
+
+    Some(Arc::new(this))
+  }
+}
+
 #[derive(Debug)]
 pub struct PersistentTrie<K: Hash + Eq + Clone, V> {
   root: Arc<PersistentNode<K, V>>,
@@ -124,15 +131,12 @@ impl<K: Hash + Eq + Clone, V> PersistentTrie<K, V> {
     }
   }
 
-  pub fn root(&self) -> Arc<PersistentNode<K, V>> {
-    self.root.clone()
+  pub fn from_root(root: Arc<PersistentNode<K, V>>) -> Self {
+    PersistentTrie { root }
   }
-}
 
-impl<K: Hash + Eq + Clone + Debug, V: Debug> PersistentTrie<K, V> {
-  pub fn maintain(&mut self) {
-    // Arc::get_mut(&mut self.root).unwrap().maintain(None);
-    self.root().maintain(None);
+  pub fn root(&self) -> Arc<PersistentNode<K, V>> {
+    self.root.clone()
   }
 }
 
@@ -145,7 +149,154 @@ impl<'a, K: Hash + Eq + Clone + 'a, V> PersistentTrie<K, V> {
       root: self.root.update(&mut pattern, value),
     }
   }
+
+  /// Removes values under `pattern` matching `f`, returns `None` if nothing
+  /// matched.
+  pub fn remove<I, F>(&self, mut pattern: I, f: F) -> Option<Self>
+  where
+    I: Iterator<Item = &'a K>,
+    F: Fn(&V) -> bool,
+  {
+    Some(PersistentTrie {
+      root: self.root.remove(&mut pattern, &f)?,
+    })
+  }
+}
+
+impl<K: Hash + Eq + Clone, V> FromIterator<(Vec<K>, V)> for PersistentTrie<K, V> {
+  fn from_iter<T: IntoIterator<Item = (Vec<K>, V)>>(iter: T) -> Self {
+    let mut builder = TrieBuilder::new();
+
+    for (pattern, value) in iter {
+      builder.insert(pattern.iter(), value);
+    }
+
+    builder.build()
+  }
+}
+
+struct BuilderNode<K: Hash + Eq + Clone, V> {
+  children: HashMap<K, BuilderNode<K, V>>,
+  values: Vec<V>,
+}
+
+impl<K: Hash + Eq + Clone, V> BuilderNode<K, V> {
+  fn new() -> Self {
+    BuilderNode {
+      children: HashMap::new(),
+      values: Vec::new(),
+    }
+  }
+
+  fn freeze(self) -> Arc<PersistentNode<K, V>> {
+    Arc::new(PersistentNode {
+      children: self
+        .children
+        .into_iter()
+        .map(|(key, child)| (key, child.freeze()))
+        .collect(),
+      values: self.values.into_iter().map(Arc::new).collect(),
+    })
+  }
+}
+
+/// Builds a trie in place and freezes it into a `PersistentTrie` at the end,
+/// loading many entries without path copying on each insert.
+pub struct TrieBuilder<K: Hash + Eq + Clone, V> {
+  root: BuilderNode<K, V>,
+}
+
+impl<K: Hash + Eq + Clone, V> Default for TrieBuilder<K, V> {
+  fn default() -> Self {
+    TrieBuilder::new()
+  }
+}
+
+impl<K: Hash + Eq + Clone, V> TrieBuilder<K, V> {
+  pub fn new() -> Self {
+    TrieBuilder {
+      root: BuilderNode::new(),
+    }
+  }
+
+  pub fn insert<'a, I>(&mut self, pattern: I, value: V)
+  where
+    I: Iterator<Item = &'a K>,
+    K: 'a,
+  {
+    let mut node = &mut self.root;
+
+    for key in pattern {
+      node = node
+        .children
+        .entry(key.clone())
+        .or_insert_with(BuilderNode::new);
+    }
+
+    node.values.push(value);
+  }
+
+  pub fn build(self) -> PersistentTrie<K, V> {
+    PersistentTrie::from_root(self.root.freeze())
+  }
 }
 
 // unsafe impl<K: Hash + Eq + Send, V: Send> Send for TrieNode<K, V> {}
 // unsafe impl<K: Hash + Eq + Sync, V: Sync> Sync for TrieNode<K, V> {}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn values(trie: &PersistentTrie<char, u32>, pattern: &str) -> Vec<u32> {
+    let mut node = trie.root();
+    for ch in pattern.chars() {
+      node = match node.child(&ch) {
+        Some(child) => child,
+        None => return vec![],
+      };
+    }
+
+    node.values.iter().map(|x| **x).collect()
+  }
+
+  fn chars(s: &str) -> Vec<char> {
+    s.chars().collect()
+  }
+
+  #[test]
+  fn builder_matches_insert() {
+    let entries = vec![("ab", 1), ("abc", 2), ("ab", 3), ("b", 4)];
+
+    let mut builder = TrieBuilder::new();
+    let mut inserted = PersistentTrie::new();
+    for (pattern, value) in &entries {
+      builder.insert(chars(pattern).iter(), *value);
+      inserted = inserted.insert(chars(pattern).iter(), *value);
+    }
+    let built = builder.build();
+
+    for pattern in &["", "a", "ab", "abc", "b", "c"] {
+      assert_eq!(values(&built, pattern), values(&inserted, pattern));
+    }
+    assert_eq!(values(&built, "ab"), vec![1, 3]);
+    assert!(built.root().child(&'c').is_none());
+  }
+
+  #[test]
+  fn insert_and_remove_keep_old_versions() {
+    let trie: PersistentTrie<char, u32> = vec![(chars("ab"), 1), (chars("ab"), 2)]
+      .into_iter()
+      .collect();
+
+    let inserted = trie.insert(chars("abc").iter(), 3);
+    let removed = inserted.remove(chars("ab").iter(), |x| *x == 1).unwrap();
+
+    assert_eq!(values(&trie, "abc"), Vec::<u32>::new());
+    assert_eq!(values(&inserted, "ab"), vec![1, 2]);
+    assert_eq!(values(&removed, "ab"), vec![2]);
+    assert_eq!(values(&removed, "abc"), vec![3]);
+    assert!(removed.remove(chars("ab").iter(), |x| *x == 1).is_none());
+    assert!(removed.remove(chars("x").iter(), |_| true).is_none());
+  }
+}
@@ -1,9 +1,18 @@
+use crate::engine::codetable::{CodeTable, CommitMode, EmptyCode};
+use crate::engine::engine::IMEngine;
+use crate::engine::mixed::MixedEngine;
+use crate::engine::pinyin::PinyinEngine;
 use crate::path::LocalConfigPath;
-use rmpv::Value;
+use async_std::sync::Mutex;
 use serde::{Deserialize, Serialize};
 use serde_json;
-use std::fs::File;
+use std::collections::HashMap;
+use std::fs::{self, File};
 use std::io::BufReader;
+use std::sync::Arc;
+
+/// Loaded engines by their names.
+pub type Engines = HashMap<String, Arc<Mutex<dyn IMEngine>>>;
 
 #[derive(Serialize, Deserialize, Debug)]
 pub enum Configuration {
@@ -12,10 +21,41 @@ pub enum Configuration {
     perfect_only: bool,
     // codetable filename
     codetable_file: String,
+    // if enable dynamic word frequency
+    #[serde(default)]
+    dynamic_word_frequency: bool,
+    // if create user phrases from consecutive commits
+    #[serde(default)]
+    auto_phrase: bool,
+    // longest code accepted, the longest code in the table if not set
+    #[serde(default)]
+    max_code_length: Option<usize>,
+    #[serde(default)]
+    commit_mode: CommitMode,
+    // what to do when the code matches nothing
+    #[serde(default)]
+    empty_code: EmptyCode,
+    // commit the first candidate when a key can't extend the code (顶字)
+    #[serde(default)]
+    top_commit: bool,
+    // key matching any single code, e.g. `z` or `?`
+    #[serde(default)]
+    wildcard: Option<char>,
+    // key starting a pinyin lookup, for characters whose code is unknown,
+    // e.g. `` ` `` or `z`
+    #[serde(default)]
+    pinyin_trigger: Option<char>,
+    // full pinyin database filename of the pinyin lookup
+    #[serde(default)]
+    pinyin_database: Option<String>,
+    // formula encoding phrases of each length, e.g. `"3": "AaBaCaCb"`, rules
+    // of 五笔 if not set
+    #[serde(default)]
+    phrase_rules: Option<HashMap<String, String>>,
   },
   Pinyin {
-    // KeyMap filename
-    scheme_file: String,
+    // shuangpin KeyMap filename, full pinyin if not set
+    scheme_file: Option<String>,
     // AssistCodes filename
     assist_file: Option<String>,
     // fuzzy syllables settings
@@ -26,17 +66,182 @@ pub enum Configuration {
     word_database: Option<String>,
     // if enable dynamic word frequency
     dynamic_word_frequency: bool,
+    // n-gram counts filename, converts the whole input into a sentence if set
+    #[serde(default)]
+    language_model: Option<String>,
+    // keys of tone 1 to 5 typed after a syllable, e.g. `12345`
+    #[serde(default)]
+    tone_keys: Option<String>,
   },
+  Mixed {
+    // engines fed with the same input, candidates of all of them are shown
+    engines: Vec<SubEngine>,
+    // keep candidates of the same text from different engines
+    #[serde(default)]
+    keep_duplicates: bool,
+  },
+}
+
+/// An engine of a mixed engine, an engine of weight 2 takes twice as many
+/// places in the candidates as one of weight 1.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SubEngine {
+  pub engine: Configuration,
+  #[serde(default = "SubEngine::default_weight")]
+  pub weight: u32,
+}
+
+impl SubEngine {
+  fn default_weight() -> u32 {
+    1
+  }
 }
 
 impl Configuration {
-  pub fn new(filename: &Value) -> Result<Self, String> {
-    let filepath = LocalConfigPath::new().file("config.json");
-    let reader = BufReader::new(File::open(filepath).unwrap());
+  pub fn new(filename: &str) -> Result<Self, String> {
+    let filepath = LocalConfigPath::new().file(filename);
+    let file =
+      File::open(&filepath).map_err(|e| format!("failed to open {}: {}", filepath.display(), e))?;
+    let reader = BufReader::new(file);
 
-    let res: Self =
-      serde_json::from_reader(reader).map_err(|_| "failed to read config.json".to_string())?;
+    let res: Self = serde_json::from_reader(reader)
+      .map_err(|e| format!("failed to read {}: {}", filepath.display(), e))?;
 
     Ok(res)
   }
+
+  /// Builds the engine described by this configuration.
+  pub fn engine(self) -> Result<Arc<Mutex<dyn IMEngine>>, String> {
+    match self {
+      Configuration::CodeTable { .. } => Ok(Arc::new(Mutex::new(CodeTable::new(self)?))),
+      Configuration::Pinyin { .. } => Ok(Arc::new(Mutex::new(PinyinEngine::new(self)?))),
+      Configuration::Mixed { .. } => Ok(Arc::new(Mutex::new(MixedEngine::new(self)?))),
+    }
+  }
+
+  /// Builds the engine described by this configuration, owned by a mixed
+  /// engine.
+  pub fn boxed_engine(self) -> Result<Box<dyn IMEngine>, String> {
+    match self {
+      Configuration::CodeTable { .. } => Ok(Box::new(CodeTable::new(self)?)),
+      Configuration::Pinyin { .. } => Ok(Box::new(PinyinEngine::new(self)?)),
+      Configuration::Mixed { .. } => Ok(Box::new(MixedEngine::new(self)?)),
+    }
+  }
+}
+
+/// Named engine configurations, e.g. `xiaohe-yx`, `wubi86` and `quanpin`.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Profiles {
+  // profile used by buffers which haven't selected one
+  pub default: String,
+  pub engines: HashMap<String, Configuration>,
+  // code table engine whose codes are shown next to candidates of an engine
+  // (反查), e.g. `quanpin` => `xiaohe-yx`
+  #[serde(default)]
+  pub reverse_lookup: HashMap<String, String>,
+}
+
+impl Profiles {
+  /// Reads profiles from `filename`, a file holding a single configuration
+  /// becomes the profile named `default`.
+  pub fn new(filename: &str) -> Result<Self, String> {
+    let filepath = LocalConfigPath::new().file(filename);
+    let content = fs::read_to_string(&filepath)
+      .map_err(|e| format!("failed to open {}: {}", filepath.display(), e))?;
+
+    Profiles::parse(&content).map_err(|e| format!("failed to read {}: {}", filepath.display(), e))
+  }
+
+  fn parse(content: &str) -> Result<Self, String> {
+    let value: serde_json::Value = serde_json::from_str(content).map_err(|e| e.to_string())?;
+
+    // deserialized from the text again for errors with line numbers
+    let res = if value.get("engines").is_some() {
+      serde_json::from_str::<Profiles>(content).map_err(|e| e.to_string())?
+    } else {
+      let config: Configuration = serde_json::from_str(content).map_err(|e| e.to_string())?;
+      let mut engines = HashMap::new();
+      engines.insert("default".to_string(), config);
+
+      Profiles {
+        default: "default".to_string(),
+        engines,
+        reverse_lookup: HashMap::new(),
+      }
+    };
+
+    if !res.engines.contains_key(&res.default) {
+      return Err(format!(
+        "default engine '{}' is not configured",
+        res.default
+      ));
+    }
+
+    Ok(res)
+  }
+
+  /// Builds every engine, returning the loaded ones and errors of the others.
+  pub fn engines(self) -> (Engines, Vec<String>) {
+    let mut engines = HashMap::new();
+    let mut errors = Vec::new();
+
+    for (name, config) in self.engines {
+      match config.engine() {
+        Ok(engine) => {
+          engines.insert(name, engine);
+        }
+        Err(err) => errors.push(format!("failed to load engine '{}': {}", name, err)),
+      }
+    }
+
+    (engines, errors)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn single_configuration() {
+    let res = Profiles::parse(
+      r#"{ "CodeTable": { "perfect_only": false, "codetable_file": "wubi86.txt" } }"#,
+    )
+    .unwrap();
+
+    assert_eq!(res.default, "default");
+    assert!(res.engines.contains_key("default"));
+  }
+
+  #[test]
+  fn field_errors_of_profiles() {
+    let err = Profiles::parse(
+      r#"{
+  "default": "wubi",
+  "engines": {
+    "wubi": { "CodeTable": { "perfect_only": "no", "codetable_file": "wubi86.txt" } }
+  }
+}"#,
+    )
+    .unwrap_err();
+
+    assert!(err.contains("expected a boolean"), "{}", err);
+    assert!(err.contains("line 4"), "{}", err);
+  }
+
+  #[test]
+  fn unknown_default() {
+    let err = Profiles::parse(
+      r#"{
+  "default": "wubi",
+  "engines": {
+    "wubi86": { "CodeTable": { "perfect_only": false, "codetable_file": "wubi86.txt" } }
+  }
+}"#,
+    )
+    .unwrap_err();
+
+    assert_eq!(err, "default engine 'wubi' is not configured");
+  }
 }
@@ -1,18 +1,23 @@
 use rmpv::Value;
 use serde::{Deserialize, Serialize};
 
-#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub enum MatchType {
   PerfectMatch,
   PrefixMatch,
   FuzzyMatch,
+  // a syllable typed as its initial only (简拼)
+  AbbreviatedMatch,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Candidate {
   pub remain_codes: Vec<char>,
   pub text: String,
   pub match_type: MatchType,
+  // the whole code of the candidate in a code table, shown next to it for
+  // wildcard matches and reverse lookup
+  pub codes: Vec<char>,
 }
 
 impl Candidate {
@@ -21,6 +26,7 @@ impl Candidate {
       remain_codes: Vec::new(),
       text: text,
       match_type: MatchType::PerfectMatch,
+      codes: Vec::new(),
     }
   }
 
@@ -29,6 +35,7 @@ impl Candidate {
       remain_codes: remain,
       text: text,
       match_type: MatchType::PrefixMatch,
+      codes: Vec::new(),
     }
   }
 
@@ -40,6 +47,11 @@ impl Candidate {
     }
   }
 
+  pub fn with_codes(mut self, codes: Vec<char>) -> Candidate {
+    self.codes = codes;
+    self
+  }
+
   pub fn message(&self) -> String {
     let mut res: String = self.text.clone();
 
@@ -58,6 +70,7 @@ impl From<MatchType> for Value {
       MatchType::PerfectMatch => Value::from("perfect"),
       MatchType::PrefixMatch => Value::from("prefix"),
       MatchType::FuzzyMatch => Value::from("fuzzy"),
+      MatchType::AbbreviatedMatch => Value::from("abbreviated"),
     }
   }
 }
@@ -76,6 +89,10 @@ impl From<Candidate> for Value {
         ),
       ),
       (Value::from("match"), Value::from(v.match_type)),
+      (
+        Value::from("codes"),
+        Value::from(v.codes.iter().collect::<String>()),
+      ),
     ])
   }
 }
@@ -94,6 +111,10 @@ impl<'a> From<&'a Candidate> for Value {
         ),
       ),
       (Value::from("match"), Value::from(v.match_type)),
+      (
+        Value::from("codes"),
+        Value::from(v.codes.iter().collect::<String>()),
+      ),
     ])
   }
 }
@@ -1,90 +1,662 @@
-use crate::data::PersistentTrie;
-use crate::engine::codetable::input_context::{CodeTableContext, ResultText};
+use crate::data::{PersistentNode, PersistentTrie, TrieBuilder};
+use crate::engine::codetable::compiled::{self, Compiled, Stamp};
+use crate::engine::codetable::input_context::{CodeTableContext, ContextOptions, ResultText};
+use crate::engine::codetable::phrase_rules::PhraseRules;
+use crate::engine::codetable::user_table::UserTable;
 use crate::engine::engine::{IMEngine, InputContext};
+use crate::engine::frequency::WordFrequency;
+use crate::engine::pinyin::{FuzzySyllables, PinyinEngine};
 use crate::engine::Configuration;
 use crate::path::LocalDataPath;
 use async_std::sync::Mutex;
+use log::{info, warn};
 use std::cell::RefCell;
-use std::collections::HashSet;
+use std::cmp;
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
 use std::io::{BufRead, BufReader};
+use std::mem;
+use std::path::Path;
 use std::rc::Rc;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+static USER_PHRASE_PRIORITY: u32 = 100;
+// below entries of the system table
+static AUTO_PHRASE_PRIORITY: u32 = 0;
+// commits further apart than this don't make up a phrase
+static PHRASE_INTERVAL: Duration = Duration::from_secs(3);
+// times a run of commits recurs before it's saved as a phrase
+static AUTO_PHRASE_COUNT: u32 = 2;
 
 pub struct CodeTable {
   table: PersistentTrie<char, ResultText>,
   keycodes: HashSet<char>,
   perfect_only: bool,
+  options: ContextOptions,
+  // learned frequencies, if dynamic word frequency is enabled
+  frequency: Option<Arc<RwLock<WordFrequency>>>,
+  // codes of each text, longest first
+  reverse: Arc<RwLock<HashMap<String, Vec<String>>>>,
+  // pinyin lookup for characters whose code is unknown
+  pinyin: Option<Arc<PinyinEngine>>,
+  user_table: UserTable,
+  rules: PhraseRules,
+  auto_phrase: bool,
+  // characters committed one by one recently, and when the last one was
+  pending_phrase: String,
+  last_commit: Option<Instant>,
+  // times each run of commits has been seen
+  phrase_counts: HashMap<String, u32>,
 }
 
 impl IMEngine for CodeTable {
   fn start_context(&self) -> Rc<RefCell<dyn InputContext>> {
-    // todo
-    Rc::new(RefCell::new(CodeTableContext::new(self.table.root())))
+    Rc::new(RefCell::new(self.context()))
   }
 
   fn start_context_async(&self) -> Arc<Mutex<dyn InputContext>> {
-    // todo
-    Arc::new(Mutex::new(CodeTableContext::new(self.table.root())))
+    Arc::new(Mutex::new(self.context()))
+  }
+
+  fn new_context(&self) -> Box<dyn InputContext> {
+    Box::new(self.context())
+  }
+
+  fn shared_context(&self) -> Box<dyn InputContext> {
+    Box::new(self.context_with(self.options.shared()))
   }
 
   fn keycodes(&self) -> HashSet<char> {
-    self.keycodes.clone()
+    let mut res = self.keycodes.clone();
+    res.extend(self.options.wildcard);
+    if let Some(pinyin) = &self.pinyin {
+      res.extend(self.options.pinyin_trigger);
+      res.extend(pinyin.keycodes());
+    }
+    res
+  }
+
+  // separators of the pinyin lookup, unless they are codes of the table
+  fn separators(&self) -> HashSet<char> {
+    match &self.pinyin {
+      Some(pinyin) => pinyin
+        .separators()
+        .into_iter()
+        .filter(|x| !self.keycodes.contains(x) && Some(*x) != self.options.wildcard)
+        .collect(),
+      None => HashSet::new(),
+    }
+  }
+
+  fn commit(&mut self, text: &str) {
+    if let Some(frequency) = &self.frequency {
+      frequency.write().unwrap().learn(text);
+    }
+
+    if self.auto_phrase {
+      self.chain_phrase(text);
+    }
+  }
+
+  fn flush(&mut self) {
+    if let Some(frequency) = &self.frequency {
+      frequency.write().unwrap().flush();
+    }
+  }
+
+  fn add_phrase(&mut self, text: &str, code: Option<&str>) -> Result<String, String> {
+    self.insert_phrase(text, code, false)
+  }
+
+  fn delete_phrase(&mut self, text: &str, code: Option<&str>) -> Result<(), String> {
+    let codes = match code {
+      Some(code) if self.user_table.contains(text, code) => vec![code.to_string()],
+      Some(code) => return Err(format!("no user phrase '{}' with code '{}'", text, code)),
+      None => self.user_table.codes(text),
+    };
+    if codes.is_empty() {
+      return Err(format!("no user phrase '{}'", text));
+    }
+
+    let mut table = PersistentTrie::from_root(self.table.root());
+    for code in &codes {
+      let chars: Vec<char> = code.chars().collect();
+
+      if let Some(removed) = table.remove(chars.iter(), |x| x.text == text) {
+        table = removed;
+      }
+    }
+
+    // the table is changed only once the user table is saved
+    self.user_table.remove(text, &codes)?;
+    self.table = table;
+    for code in &codes {
+      self.remove_reverse(text, code);
+      info!("delete user phrase {} with code {}", text, code);
+    }
+
+    Ok(())
+  }
+
+  fn reverse_lookup(&self, text: &str) -> Vec<String> {
+    self
+      .reverse
+      .read()
+      .unwrap()
+      .get(text)
+      .cloned()
+      .unwrap_or_default()
   }
 }
 
 impl CodeTable {
-  pub fn new(config: Configuration) -> Option<CodeTable> {
+  pub fn new(config: Configuration) -> Result<CodeTable, String> {
     if let Configuration::CodeTable {
       perfect_only,
       codetable_file,
+      dynamic_word_frequency,
+      auto_phrase,
+      max_code_length,
+      commit_mode,
+      empty_code,
+      top_commit,
+      wildcard,
+      pinyin_trigger,
+      pinyin_database,
+      phrase_rules,
     } = config
     {
-      let mut res = CodeTable::table_file(&codetable_file);
+      let mut res = CodeTable::table_file(&codetable_file)?;
       res.perfect_only = perfect_only;
-      Some(res)
+      res.auto_phrase = auto_phrase;
+      if let Some(max_length) = max_code_length {
+        res.options.max_length = max_length;
+      }
+      res.options.commit_mode = commit_mode;
+      res.options.empty_code = empty_code;
+      res.options.top_commit = top_commit;
+      res.options.wildcard = wildcard;
+      res.options.pinyin_trigger = pinyin_trigger;
+      if let Some(rules) = phrase_rules {
+        res.rules = PhraseRules::parse(rules.iter().map(|(k, v)| (k.as_str(), v.as_str())))?;
+      }
+      res.check_options()?;
+      match (pinyin_trigger, pinyin_database) {
+        (Some(_), Some(database)) => {
+          res.pinyin = Some(Arc::new(PinyinEngine::database_files(
+            None,
+            FuzzySyllables::new(vec![])?,
+            vec![],
+            None,
+            &[database],
+          )?));
+        }
+        (Some(_), None) => return Err("pinyin_trigger requires pinyin_database".to_string()),
+        (None, _) => {}
+      }
+      if dynamic_word_frequency {
+        res.frequency = Some(WordFrequency::shared(&format!("{}.freq", codetable_file)));
+      }
+      Ok(res)
     } else {
-      None
+      Err("expect code table configuration".to_string())
     }
   }
 
-  pub fn table_file(filename: &str) -> CodeTable {
-    let mut code_table = CodeTable {
+  // the trigger key can't be a key typing codes
+  fn check_options(&self) -> Result<(), String> {
+    if let Some(trigger) = self.options.pinyin_trigger {
+      if self.keycodes.contains(&trigger) {
+        return Err(format!(
+          "pinyin_trigger '{}' is a keycode of the table",
+          trigger
+        ));
+      }
+      if Some(trigger) == self.options.wildcard {
+        return Err(format!("pinyin_trigger '{}' is the wildcard", trigger));
+      }
+    }
+
+    Ok(())
+  }
+
+  // a table without codes, on top of which user phrases are added
+  fn empty(user_table: UserTable) -> CodeTable {
+    CodeTable {
       table: PersistentTrie::new(),
       keycodes: HashSet::new(),
       perfect_only: false,
-    };
+      options: ContextOptions::default(),
+      frequency: None,
+      reverse: Arc::new(RwLock::new(HashMap::new())),
+      pinyin: None,
+      user_table,
+      rules: PhraseRules::default(),
+      auto_phrase: false,
+      pending_phrase: String::new(),
+      last_commit: None,
+      phrase_counts: HashMap::new(),
+    }
+  }
+
+  pub fn table_file(filename: &str) -> Result<CodeTable, String> {
+    let mut code_table = CodeTable::empty(UserTable::load(&format!("{}.user", filename)));
     let filepath = LocalDataPath::new().sub("codetable").file(filename);
+    let stamp = Stamp::of(&filepath)?;
+    let cache = LocalDataPath::new()
+      .sub("cache")
+      .file(&format!("{}.bin", filename));
+
+    match compiled::load(&cache, &stamp) {
+      Ok(compiled) => {
+        code_table.table = PersistentTrie::from_root(compiled.root);
+        code_table.keycodes = compiled.keycodes;
+        code_table.options.max_length = compiled.max_length;
+        code_table.reverse = Arc::new(RwLock::new(compiled.reverse));
+      }
+      Err(err) => {
+        info!("rebuild compiled code table: {}", err);
+
+        code_table.table = CodeTable::parse_table(filename, &filepath)?;
+        code_table.index(&code_table.table.root(), &mut Vec::new());
+        let compiled = Compiled {
+          root: code_table.table.root(),
+          keycodes: code_table.keycodes.clone(),
+          max_length: code_table.options.max_length,
+          reverse: code_table.reverse.read().unwrap().clone(),
+        };
+        if let Err(err) = compiled::save(&cache, &stamp, &compiled) {
+          warn!("{}", err);
+        }
+      }
+    }
 
-    let reader = BufReader::new(File::open(filepath).unwrap());
+    // user phrases on top of the system table
+    for phrase in code_table.user_table.phrases().to_vec() {
+      let code = phrase.code;
+      code_table.options.max_length = cmp::max(code_table.options.max_length, code.chars().count());
+      code_table.table = code_table.table.insert(
+        code.chars().collect::<Vec<char>>().iter(),
+        ResultText {
+          text: phrase.text.clone(),
+          priority: if phrase.auto {
+            AUTO_PHRASE_PRIORITY
+          } else {
+            USER_PHRASE_PRIORITY
+          },
+        },
+      );
+      code_table.add_reverse(&phrase.text, &code);
+    }
+
+    Ok(code_table)
+  }
 
-    for l in reader.lines() {
+  fn parse_table(
+    filename: &str,
+    filepath: &Path,
+  ) -> Result<PersistentTrie<char, ResultText>, String> {
+    let mut builder = TrieBuilder::new();
+
+    let file = File::open(filepath)
+      .map_err(|e| format!("failed to open code table {}: {}", filepath.display(), e))?;
+    let reader = BufReader::new(file);
+
+    for (lineno, l) in reader.lines().enumerate() {
       if let Ok(line) = l {
-        let v: Vec<&str> = line.trim().split('\t').collect();
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+          continue;
+        }
+        let v: Vec<&str> = line.split('\t').collect();
 
         let priority;
-        if v.len() == 2 {
+        if v.len() < 2 {
+          return Err(format!(
+            "{}:{}: expect text and code separated by tab",
+            filename,
+            lineno + 1
+          ));
+        } else if v.len() == 2 {
           priority = 100;
-        // panic!("line should be splited into two parts, but got {}", v.len());
         } else {
-          priority = v[2].parse::<u32>().unwrap();
+          priority = v[2]
+            .parse::<u32>()
+            .map_err(|_| format!("{}:{}: invalid priority '{}'", filename, lineno + 1, v[2]))?;
         }
 
-        code_table.table = code_table.table.insert(
+        builder.insert(
           v[1].chars().collect::<Vec<char>>().iter(),
           ResultText {
             text: v[0].to_string(),
             priority: priority,
           },
         );
-        for ch in v[1].chars().collect::<Vec<char>>() {
-          code_table.keycodes.insert(ch);
-        }
       }
     }
 
-    code_table.table.maintain();
+    Ok(builder.build())
+  }
+
+  // collects keycodes, max code length and codes of each text of the system
+  // table
+  fn index(&mut self, node: &Arc<PersistentNode<char, ResultText>>, codes: &mut Vec<char>) {
+    if !node.values.is_empty() {
+      self.options.max_length = cmp::max(self.options.max_length, codes.len());
+    }
+
+    for value in &node.values {
+      self.add_reverse(&value.text, &codes.iter().collect::<String>());
+    }
+
+    for (ch, child) in &node.children {
+      self.keycodes.insert(*ch);
+
+      codes.push(*ch);
+      self.index(child, codes);
+      codes.pop();
+    }
+  }
+
+  fn add_reverse(&mut self, text: &str, code: &str) {
+    let mut reverse = self.reverse.write().unwrap();
+    let codes = reverse.entry(text.to_string()).or_default();
+    let len = code.chars().count();
+    let pos = codes
+      .iter()
+      .position(|x| x.chars().count() < len)
+      .unwrap_or(codes.len());
+
+    codes.insert(pos, code.to_string());
+  }
+
+  fn remove_reverse(&mut self, text: &str, code: &str) {
+    if let Some(codes) = self.reverse.write().unwrap().get_mut(text) {
+      codes.retain(|x| x != code);
+    }
+  }
+
+  fn context(&self) -> CodeTableContext {
+    self.context_with(self.options)
+  }
+
+  fn context_with(&self, options: ContextOptions) -> CodeTableContext {
+    CodeTableContext::new(
+      self.table.root(),
+      self.perfect_only,
+      self.frequency.clone(),
+      options,
+      self.pinyin.clone(),
+      self.reverse.clone(),
+    )
+  }
+
+  fn lookup(&self, codes: &[char]) -> Vec<Arc<ResultText>> {
+    let mut node = self.table.root();
+
+    for ch in codes {
+      node = match node.child(ch) {
+        Some(child) => child,
+        None => return vec![],
+      };
+    }
+
+    node.values.clone()
+  }
+
+  fn insert_phrase(
+    &mut self,
+    text: &str,
+    code: Option<&str>,
+    auto: bool,
+  ) -> Result<String, String> {
+    let code = match code {
+      Some(code) => code.to_string(),
+      None => self.encode(text)?,
+    };
+    let chars: Vec<char> = code.chars().collect();
 
+    if let Some(ch) = chars.iter().find(|ch| !self.keycodes.contains(ch)) {
+      return Err(format!("'{}' is not a keycode of this table", ch));
+    }
+    if chars.len() > self.options.max_length {
+      return Err(format!(
+        "code '{}' is longer than max code length {}",
+        code, self.options.max_length
+      ));
+    }
+    if self.lookup(&chars).iter().any(|x| x.text == text) {
+      return Err(format!("'{}' already exists with code '{}'", text, code));
+    }
+
+    self.user_table.add(text, &code, auto)?;
+    self.table = self.table.insert(
+      chars.iter(),
+      ResultText {
+        text: text.to_string(),
+        priority: if auto {
+          AUTO_PHRASE_PRIORITY
+        } else {
+          USER_PHRASE_PRIORITY
+        },
+      },
+    );
+    self.add_reverse(text, &code);
+    info!(
+      "add user phrase {} with code {}, auto: {}",
+      text, code, auto
+    );
+
+    Ok(code)
+  }
+
+  /// Encodes a phrase from the full codes of its characters by the phrase
+  /// rules of the table.
+  pub fn encode(&self, text: &str) -> Result<String, String> {
+    let chars: Vec<char> = text.chars().collect();
+    let reverse = self.reverse.read().unwrap();
+    let codes = chars
+      .iter()
+      .map(|ch| {
+        reverse
+          .get(&ch.to_string())
+          .and_then(|codes| codes.first())
+          .map(|code| code.as_str())
+          .ok_or_else(|| format!("no code for '{}'", ch))
+      })
+      .collect::<Result<Vec<&str>, String>>()?;
+
+    self.rules.encode(&codes)
+  }
+
+  // collects characters committed one after another, and turns them into a
+  // phrase once the chain breaks and it has been seen before
+  fn chain_phrase(&mut self, text: &str) {
+    let now = Instant::now();
+    let expired = self
+      .last_commit
+      .is_none_or(|last| now.duration_since(last) > PHRASE_INTERVAL);
+    self.last_commit = Some(now);
+
+    let single = text.chars().count() == 1;
+    if expired || !single {
+      self.finish_phrase();
+    }
+    // runs longer than the rules cover are kept one character too long, and
+    // skipped when they end
+    if single && self.pending_phrase.chars().count() <= self.rules.max_length() {
+      self.pending_phrase.push_str(text);
+    }
+  }
+
+  fn finish_phrase(&mut self) {
+    let phrase = mem::take(&mut self.pending_phrase);
+    let len = phrase.chars().count();
+    if len < 2 || len > self.rules.max_length() {
+      return;
+    }
+
+    let count = self.phrase_counts.entry(phrase.clone()).or_default();
+    *count += 1;
+    if *count < AUTO_PHRASE_COUNT {
+      return;
+    }
+
+    self.phrase_counts.remove(&phrase);
+    if let Err(err) = self.insert_phrase(&phrase, None, true) {
+      info!("skip auto phrase {}: {}", phrase, err);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::fs;
+  use std::path::PathBuf;
+
+  fn parse(content: &str) -> Result<PersistentTrie<char, ResultText>, String> {
+    let path = std::env::temp_dir().join(format!("ime-neovim-table-{}.txt", std::process::id()));
+    fs::write(&path, content).unwrap();
+    let res = CodeTable::parse_table("test.txt", &path);
+    fs::remove_file(&path).unwrap();
+
+    res
+  }
+
+  #[test]
+  fn parse_table() {
+    let table = parse("# 码表\n\n中\tvs\n国\tgo\t10\n  \n").unwrap();
+    let node = table.root().child(&'g').unwrap().child(&'o').unwrap();
+
+    assert_eq!(node.values[0].text, "国");
+    assert_eq!(node.values[0].priority, 10);
+    assert_eq!(
+      parse("中\tvs\n国\n").err().unwrap(),
+      "test.txt:2: expect text and code separated by tab"
+    );
+    assert_eq!(
+      parse("中\tvs\tx\n").err().unwrap(),
+      "test.txt:1: invalid priority 'x'"
+    );
+  }
+
+  // a table of 中国人民, with user phrases in a temporary file
+  fn code_table(name: &str) -> (CodeTable, PathBuf) {
+    let path = std::env::temp_dir().join(format!(
+      "ime-neovim-table-{}-{}.user",
+      name,
+      std::process::id()
+    ));
+    let mut code_table = CodeTable::empty(UserTable::load_path(path.clone()));
+    code_table.table = parse("中\tvsll\n国\tgoll\n人\trfrr\n民\tmbmm\n").unwrap();
+    let root = code_table.table.root();
+    code_table.index(&root, &mut Vec::new());
+
+    (code_table, path)
+  }
+
+  fn texts(code_table: &CodeTable, code: &str) -> Vec<String> {
+    let codes: Vec<char> = code.chars().collect();
     code_table
+      .lookup(&codes)
+      .iter()
+      .map(|x| x.text.clone())
+      .collect()
+  }
+
+  #[test]
+  fn add_and_delete_phrases() {
+    let (mut code_table, path) = code_table("phrase");
+
+    assert_eq!(code_table.add_phrase("中国", None).unwrap(), "vsgo");
+    assert_eq!(code_table.add_phrase("中国", Some("vsg")).unwrap(), "vsg");
+    assert_eq!(texts(&code_table, "vsgo"), vec!["中国"]);
+    assert_eq!(code_table.reverse_lookup("中国"), vec!["vsgo", "vsg"]);
+    assert_eq!(
+      code_table.add_phrase("中国", None).err().unwrap(),
+      "'中国' already exists with code 'vsgo'"
+    );
+    assert!(code_table.add_phrase("中国", Some("v1")).is_err());
+    assert!(code_table.add_phrase("中国", Some("vsgoll")).is_err());
+
+    code_table.delete_phrase("中国", Some("vsg")).unwrap();
+    assert!(texts(&code_table, "vsg").is_empty());
+    assert_eq!(
+      UserTable::load_path(path.clone()).codes("中国"),
+      vec!["vsgo"]
+    );
+
+    code_table.delete_phrase("中国", None).unwrap();
+    assert!(texts(&code_table, "vsgo").is_empty());
+    assert!(code_table.reverse_lookup("中国").is_empty());
+    assert!(code_table.delete_phrase("中国", None).is_err());
+    fs::remove_file(&path).unwrap();
+  }
+
+  #[test]
+  fn auto_phrases() {
+    let (mut code_table, path) = code_table("auto");
+    code_table.auto_phrase = true;
+
+    for _ in 0..AUTO_PHRASE_COUNT {
+      assert!(texts(&code_table, "vsgo").is_empty());
+      code_table.commit("中");
+      code_table.commit("国");
+      // a commit of many characters ends the chain
+      code_table.commit("人民");
+    }
+    let node = code_table.lookup(&['v', 's', 'g', 'o']);
+    assert_eq!(node[0].text, "中国");
+    assert_eq!(node[0].priority, AUTO_PHRASE_PRIORITY);
+    assert!(code_table.user_table.phrases()[0].auto);
+
+    // so does a pause
+    code_table.commit("人");
+    code_table.last_commit = Instant::now().checked_sub(PHRASE_INTERVAL * 2);
+    code_table.commit("民");
+    assert_eq!(code_table.pending_phrase, "民");
+    fs::remove_file(&path).unwrap();
+  }
+
+  #[test]
+  fn option_keys() {
+    let (mut code_table, _) = code_table("options");
+    code_table.options.wildcard = Some('?');
+    code_table.options.pinyin_trigger = Some('`');
+    assert!(code_table.check_options().is_ok());
+
+    code_table.options.pinyin_trigger = Some('v');
+    assert_eq!(
+      code_table.check_options().err().unwrap(),
+      "pinyin_trigger 'v' is a keycode of the table"
+    );
+    code_table.options.pinyin_trigger = Some('?');
+    assert_eq!(
+      code_table.check_options().err().unwrap(),
+      "pinyin_trigger '?' is the wildcard"
+    );
+  }
+
+  #[test]
+  fn reverse_codes_longest_first() {
+    let (mut code_table, _) = code_table("reverse");
+    assert_eq!(code_table.reverse_lookup("中"), vec!["vsll"]);
+    assert!(code_table.reverse_lookup("中国").is_empty());
+
+    code_table.add_reverse("中", "vs");
+    code_table.add_reverse("中", "vsl");
+    // codes of the same length stay in the order added
+    code_table.add_reverse("中", "vsgg");
+    assert_eq!(
+      code_table.reverse_lookup("中"),
+      vec!["vsll", "vsgg", "vsl", "vs"]
+    );
+
+    code_table.remove_reverse("中", "vsl");
+    code_table.remove_reverse("国", "vs");
+    assert_eq!(code_table.reverse_lookup("中"), vec!["vsll", "vsgg", "vs"]);
+    assert_eq!(code_table.reverse_lookup("国"), vec!["goll"]);
   }
 }
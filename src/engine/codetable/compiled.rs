@@ -0,0 +1,410 @@
+use crate::data::PersistentNode;
+use crate::engine::codetable::input_context::ResultText;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+
+// Compiled code tables hold the trie of a text table in preorder, each node
+// as its values (`len text priority`) followed by its children (`key node`),
+// then the index of the trie: keycodes (`count key...`), the max code length
+// and the codes of each text (`count (text count code...)...`), strings as
+// `len bytes` and all integers little endian u32. Loading one rebuilds every
+// node exactly once instead of parsing the text and path copying on each
+// insert, and takes the index as it is instead of walking the trie.
+
+static MAGIC: &[u8; 8] = b"IMECT\x00\x00\x02";
+
+type NodeType = Arc<PersistentNode<char, ResultText>>;
+
+/// The trie of a code table along with what's indexed from it.
+pub struct Compiled {
+  pub root: NodeType,
+  pub keycodes: HashSet<char>,
+  pub max_length: usize,
+  // codes of each text, longest first
+  pub reverse: HashMap<String, Vec<String>>,
+}
+
+/// Identifies the text table a compiled table was built from, a compiled
+/// table with another stamp is stale.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Stamp {
+  modified: u64,
+  len: u64,
+}
+
+impl Stamp {
+  pub fn of(path: &Path) -> Result<Stamp, String> {
+    let metadata =
+      fs::metadata(path).map_err(|e| format!("failed to stat {}: {}", path.display(), e))?;
+    let modified = metadata
+      .modified()
+      .ok()
+      .and_then(|x| x.duration_since(UNIX_EPOCH).ok())
+      .map_or(0, |x| x.as_nanos() as u64);
+
+    Ok(Stamp {
+      modified,
+      len: metadata.len(),
+    })
+  }
+}
+
+/// Writes the compiled table to `path`, replacing the old one only once the
+/// new one is complete.
+pub fn save(path: &Path, stamp: &Stamp, compiled: &Compiled) -> Result<(), String> {
+  let error = |e: std::io::Error| format!("failed to save {}: {}", path.display(), e);
+
+  if let Some(dir) = path.parent() {
+    fs::create_dir_all(dir).map_err(error)?;
+  }
+
+  let tmp = path.with_extension("tmp");
+  {
+    let mut writer = BufWriter::new(File::create(&tmp).map_err(error)?);
+    writer.write_all(MAGIC).map_err(error)?;
+    writer
+      .write_all(&stamp.modified.to_le_bytes())
+      .map_err(error)?;
+    writer.write_all(&stamp.len.to_le_bytes()).map_err(error)?;
+    write_node(&mut writer, &compiled.root).map_err(error)?;
+    write_index(&mut writer, compiled).map_err(error)?;
+    writer.flush().map_err(error)?;
+  }
+
+  fs::rename(&tmp, path).map_err(error)
+}
+
+/// Reads the compiled table from `path`, fails if it's missing, stale or
+/// broken.
+pub fn load(path: &Path, stamp: &Stamp) -> Result<Compiled, String> {
+  let data = fs::read(path).map_err(|e| format!("failed to load {}: {}", path.display(), e))?;
+  let error = |e: String| format!("failed to load {}: {}", path.display(), e);
+  let mut reader = Reader { data: &data };
+
+  if reader.take(MAGIC.len()).ok() != Some(&MAGIC[..]) {
+    return Err(format!("{} is not a compiled code table", path.display()));
+  }
+
+  let saved = Stamp {
+    modified: reader.u64().map_err(error)?,
+    len: reader.u64().map_err(error)?,
+  };
+  if &saved != stamp {
+    return Err(format!("{} is stale", path.display()));
+  }
+
+  let root = read_node(&mut reader).map_err(error)?;
+  let compiled = read_index(&mut reader, root).map_err(error)?;
+
+  // trailing bytes mean the file isn't what we wrote
+  if !reader.data.is_empty() {
+    return Err(format!("{} has trailing data", path.display()));
+  }
+
+  Ok(compiled)
+}
+
+fn write_node<W: Write>(writer: &mut W, node: &NodeType) -> std::io::Result<()> {
+  writer.write_all(&(node.values.len() as u32).to_le_bytes())?;
+  for value in &node.values {
+    write_str(writer, &value.text)?;
+    writer.write_all(&value.priority.to_le_bytes())?;
+  }
+
+  writer.write_all(&(node.children.len() as u32).to_le_bytes())?;
+  for (key, child) in &node.children {
+    writer.write_all(&(*key as u32).to_le_bytes())?;
+    write_node(writer, child)?;
+  }
+
+  Ok(())
+}
+
+fn write_index<W: Write>(writer: &mut W, compiled: &Compiled) -> std::io::Result<()> {
+  writer.write_all(&(compiled.keycodes.len() as u32).to_le_bytes())?;
+  for key in &compiled.keycodes {
+    writer.write_all(&(*key as u32).to_le_bytes())?;
+  }
+
+  writer.write_all(&(compiled.max_length as u32).to_le_bytes())?;
+
+  writer.write_all(&(compiled.reverse.len() as u32).to_le_bytes())?;
+  for (text, codes) in &compiled.reverse {
+    write_str(writer, text)?;
+    writer.write_all(&(codes.len() as u32).to_le_bytes())?;
+    for code in codes {
+      write_str(writer, code)?;
+    }
+  }
+
+  Ok(())
+}
+
+fn write_str<W: Write>(writer: &mut W, s: &str) -> std::io::Result<()> {
+  writer.write_all(&(s.len() as u32).to_le_bytes())?;
+  writer.write_all(s.as_bytes())
+}
+
+// nodes are read with a stack of their own, a deep trie of a broken or
+// hostile file can't overflow the call stack
+fn read_node(reader: &mut Reader) -> Result<NodeType, String> {
+  // nodes whose children are being read, with their keys in their parents
+  // and the number of children left
+  let (root, count) = read_values(reader)?;
+  let mut stack = vec![(None, root, count)];
+
+  loop {
+    let top = stack.last_mut().unwrap();
+    if top.2 > 0 {
+      top.2 -= 1;
+      let key = reader.char()?;
+      let (node, count) = read_values(reader)?;
+      stack.push((Some(key), node, count));
+      continue;
+    }
+
+    let (key, node, _) = stack.pop().unwrap();
+    match (key, stack.last_mut()) {
+      (Some(key), Some(parent)) => {
+        parent.1.children.insert(key, Arc::new(node));
+      }
+      _ => return Ok(Arc::new(node)),
+    }
+  }
+}
+
+// a node with its values, and the number of its children to read
+fn read_values(reader: &mut Reader) -> Result<(PersistentNode<char, ResultText>, usize), String> {
+  let mut node = PersistentNode::new();
+
+  for _ in 0..reader.u32()? {
+    node.values.push(Arc::new(ResultText {
+      text: reader.string()?,
+      priority: reader.u32()?,
+    }));
+  }
+
+  // a child takes at least 8 bytes, a larger count is broken and mustn't
+  // allocate
+  let count = reader.u32()? as usize;
+  node.children = HashMap::with_capacity(count.min(reader.data.len() / 8));
+
+  Ok((node, count))
+}
+
+fn read_index(reader: &mut Reader, root: NodeType) -> Result<Compiled, String> {
+  let mut keycodes = HashSet::new();
+  for _ in 0..reader.u32()? {
+    keycodes.insert(reader.char()?);
+  }
+
+  let max_length = reader.u32()? as usize;
+
+  let mut reverse = HashMap::new();
+  for _ in 0..reader.u32()? {
+    let text = reader.string()?;
+    let mut codes = Vec::new();
+    for _ in 0..reader.u32()? {
+      codes.push(reader.string()?);
+    }
+    reverse.insert(text, codes);
+  }
+
+  Ok(Compiled {
+    root,
+    keycodes,
+    max_length,
+    reverse,
+  })
+}
+
+// reads a compiled table in memory, every length is checked against the
+// bytes left so a broken file fails instead of allocating what it claims
+struct Reader<'a> {
+  data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+  fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+    if len > self.data.len() {
+      return Err(format!(
+        "expect {} bytes but only {} left",
+        len,
+        self.data.len()
+      ));
+    }
+
+    let (res, rest) = self.data.split_at(len);
+    self.data = rest;
+    Ok(res)
+  }
+
+  fn u32(&mut self) -> Result<u32, String> {
+    let mut buf = [0u8; 4];
+    buf.copy_from_slice(self.take(4)?);
+    Ok(u32::from_le_bytes(buf))
+  }
+
+  fn u64(&mut self) -> Result<u64, String> {
+    let mut buf = [0u8; 8];
+    buf.copy_from_slice(self.take(8)?);
+    Ok(u64::from_le_bytes(buf))
+  }
+
+  fn char(&mut self) -> Result<char, String> {
+    char::try_from(self.u32()?).map_err(|e| e.to_string())
+  }
+
+  fn string(&mut self) -> Result<String, String> {
+    let len = self.u32()? as usize;
+    String::from_utf8(self.take(len)?.to_vec()).map_err(|e| e.to_string())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::data::TrieBuilder;
+  use std::path::PathBuf;
+
+  fn filepath(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("ime-neovim-{}-{}.bin", name, std::process::id()))
+  }
+
+  fn stamp() -> Stamp {
+    Stamp {
+      modified: 1,
+      len: 2,
+    }
+  }
+
+  fn compiled() -> Compiled {
+    let mut builder = TrieBuilder::new();
+    for (code, text) in &[("vs", "中"), ("vsll", "中"), ("go", "国"), ("vsgo", "中国")] {
+      builder.insert(
+        code.chars().collect::<Vec<char>>().iter(),
+        ResultText {
+          text: text.to_string(),
+          priority: 100,
+        },
+      );
+    }
+
+    let mut reverse = HashMap::new();
+    reverse.insert("中".to_string(), vec!["vsll".to_string(), "vs".to_string()]);
+    reverse.insert("国".to_string(), vec!["go".to_string()]);
+    reverse.insert("中国".to_string(), vec!["vsgo".to_string()]);
+
+    Compiled {
+      root: builder.build().root(),
+      keycodes: "vslgo".chars().collect(),
+      max_length: 4,
+      reverse,
+    }
+  }
+
+  fn texts(root: &NodeType, code: &str) -> Vec<String> {
+    let mut node = root.clone();
+    for ch in code.chars() {
+      node = node.child(&ch).unwrap();
+    }
+    node.values.iter().map(|x| x.text.clone()).collect()
+  }
+
+  #[test]
+  fn round_trip() {
+    let path = filepath("round-trip");
+    let expected = compiled();
+    save(&path, &stamp(), &expected).unwrap();
+    let res = load(&path, &stamp()).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(texts(&res.root, "vs"), vec!["中"]);
+    assert_eq!(texts(&res.root, "vsgo"), vec!["中国"]);
+    assert!(texts(&res.root, "v").is_empty());
+    assert_eq!(res.keycodes, expected.keycodes);
+    assert_eq!(res.max_length, 4);
+    assert_eq!(res.reverse, expected.reverse);
+  }
+
+  #[test]
+  fn stale() {
+    let path = filepath("stale");
+    save(&path, &stamp(), &compiled()).unwrap();
+    let res = load(
+      &path,
+      &Stamp {
+        modified: 1,
+        len: 3,
+      },
+    );
+    fs::remove_file(&path).unwrap();
+
+    assert!(res.is_err());
+  }
+
+  #[test]
+  fn broken() {
+    let path = filepath("broken");
+    save(&path, &stamp(), &compiled()).unwrap();
+    let data = fs::read(&path).unwrap();
+
+    // truncated
+    fs::write(&path, &data[..data.len() - 1]).unwrap();
+    assert!(load(&path, &stamp()).is_err());
+
+    // a huge count of values at the root
+    let mut corrupt = data.clone();
+    corrupt[24..28].copy_from_slice(&u32::MAX.to_le_bytes());
+    fs::write(&path, &corrupt).unwrap();
+    assert!(load(&path, &stamp()).is_err());
+
+    // trailing data
+    let mut trailing = data.clone();
+    trailing.push(0);
+    fs::write(&path, &trailing).unwrap();
+    assert!(load(&path, &stamp()).is_err());
+
+    fs::remove_file(&path).unwrap();
+  }
+
+  #[test]
+  fn deep_trie() {
+    let path = filepath("deep");
+    let depth = 2000;
+
+    // a chain of nodes with one child each, then an empty index
+    let mut data = MAGIC.to_vec();
+    data.extend_from_slice(&stamp().modified.to_le_bytes());
+    data.extend_from_slice(&stamp().len.to_le_bytes());
+    for _ in 0..depth {
+      for x in &[0, 1, 'v' as u32] {
+        data.extend_from_slice(&x.to_le_bytes());
+      }
+    }
+    data.extend_from_slice(&1u32.to_le_bytes());
+    write_str(&mut data, "中").unwrap();
+    for x in &[100u32, 0, 0, 0, 0] {
+      data.extend_from_slice(&x.to_le_bytes());
+    }
+    fs::write(&path, &data).unwrap();
+
+    // far less stack than reading a node by a call takes
+    let load_path = path.clone();
+    let res = std::thread::Builder::new()
+      .stack_size(64 * 1024)
+      .spawn(move || load(&load_path, &stamp()))
+      .unwrap()
+      .join()
+      .unwrap();
+    fs::remove_file(&path).unwrap();
+
+    let code = "v".repeat(depth);
+    assert_eq!(texts(&res.unwrap().root, &code), vec!["中"]);
+  }
+}
@@ -1,10 +1,16 @@
 use crate::data::PersistentNode;
 use crate::engine::candidate::Candidate;
-use crate::engine::engine::{BackspaceResult, ContextId, InputContext};
+use crate::engine::engine::{
+  BackspaceResult, ConfirmResult, ContextId, CursorMotion, InputContext, CANDIDATE_BATCH,
+};
+use crate::engine::frequency::WordFrequency;
+use crate::engine::pinyin::{PinyinContext, PinyinEngine};
 use log::info;
-use std::cmp;
-use std::collections::LinkedList;
-use std::sync::Arc;
+use serde::{Deserialize, Serialize};
+use std::cmp::{self, Reverse};
+use std::collections::{BinaryHeap, HashMap};
+use std::mem;
+use std::sync::{Arc, RwLock};
 
 #[derive(Debug)]
 pub struct ResultText {
@@ -12,153 +18,459 @@ pub struct ResultText {
   pub priority: u32,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CommitMode {
+  // commit only by space or digits
+  #[default]
+  Manual,
+  // commit as soon as the code has exactly one candidate
+  AutoOnUnique,
+  // commit the only perfect match once the code reaches max length
+  AutoOnMaxLength,
+}
+
+/// What to do with the input when it matches no candidate.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EmptyCode {
+  // drop the whole input (空码清屏)
+  Clear,
+  // keep the raw letters, which are committed as they are
+  #[default]
+  Keep,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ContextOptions {
+  // longest code accepted, further keys can't extend the code
+  pub max_length: usize,
+  pub commit_mode: CommitMode,
+  pub empty_code: EmptyCode,
+  // commit the first candidate when a key can't extend the code (顶字)
+  pub top_commit: bool,
+  // key matching any single code
+  pub wildcard: Option<char>,
+  // first key of the input starting a pinyin lookup
+  pub pinyin_trigger: Option<char>,
+}
+
+impl ContextOptions {
+  /// Options of a context fed along with others, e.g. in a mixed engine,
+  /// which keeps every key so its input stays the same as theirs.
+  pub fn shared(self) -> ContextOptions {
+    ContextOptions {
+      max_length: usize::MAX,
+      commit_mode: CommitMode::Manual,
+      empty_code: EmptyCode::Keep,
+      top_commit: false,
+      ..self
+    }
+  }
+}
+
 type NodeType = Arc<PersistentNode<char, ResultText>>;
+
+// a node matched by the input, and the codes leading to it
+struct Matched {
+  node: NodeType,
+  codes: Arc<Vec<char>>,
+}
+
 pub struct CodeTableContext {
   id: ContextId,
-  current: NodeType,
+  // nodes matched by each prefix of the input, from the root, wildcards
+  // match many of them
+  path: Vec<Vec<Matched>>,
   input_sequence: Vec<char>,
+  // number of input keys before the cursor
+  cursor: usize,
   overflow_number: u32,
+  // only show candidates whose code is exactly the input
+  perfect_only: bool,
+  frequency: Option<Arc<RwLock<WordFrequency>>>,
+  options: ContextOptions,
+  // text committed by the context itself, not taken yet
+  committed: String,
+  // candidates of the input generated so far, level by level
+  generated: Vec<FlattenItem>,
+  // nodes of the level to generate candidates from, and the number of their
+  // values generated already
+  frontier: Vec<QueueItem>,
+  taken: usize,
+  pinyin_engine: Option<Arc<PinyinEngine>>,
+  // pinyin lookup started by the trigger key, which takes over the input
+  pinyin: Option<PinyinContext>,
+  // codes of each text, longest first
+  reverse: Arc<RwLock<HashMap<String, Vec<String>>>>,
 }
 
 impl CodeTableContext {
-  pub fn new(node: NodeType) -> CodeTableContext {
+  pub fn new(
+    node: NodeType,
+    perfect_only: bool,
+    frequency: Option<Arc<RwLock<WordFrequency>>>,
+    options: ContextOptions,
+    pinyin_engine: Option<Arc<PinyinEngine>>,
+    reverse: Arc<RwLock<HashMap<String, Vec<String>>>>,
+  ) -> CodeTableContext {
     CodeTableContext {
       id: ContextId::new(),
-      current: node,
+      path: vec![vec![Matched {
+        node,
+        codes: Arc::new(Vec::new()),
+      }]],
       input_sequence: Vec::new(),
+      cursor: 0,
       overflow_number: 0,
+      perfect_only,
+      frequency,
+      options,
+      committed: String::new(),
+      generated: Vec::new(),
+      frontier: Vec::new(),
+      taken: 0,
+      pinyin_engine,
+      pinyin: None,
+      reverse,
+    }
+  }
+
+  fn current(&self) -> &Vec<Matched> {
+    self.path.last().unwrap()
+  }
+
+  // nodes matched after feeding `ch`
+  fn step(&self, ch: char) -> Vec<Matched> {
+    let mut res = Vec::new();
+
+    for matched in self.current() {
+      let mut push = |key: &char, node: &NodeType| {
+        let mut codes = matched.codes.as_ref().clone();
+        codes.push(*key);
+
+        res.push(Matched {
+          node: node.clone(),
+          codes: Arc::new(codes),
+        });
+      };
+
+      if Some(ch) == self.options.wildcard {
+        for (key, child) in &matched.node.children {
+          push(key, child);
+        }
+      } else if let Some(child) = matched.node.child(&ch) {
+        push(&ch, &child);
+      }
+    }
+
+    res
+  }
+
+  fn has_wildcard(&self) -> bool {
+    match self.options.wildcard {
+      Some(wildcard) => self.input_sequence.contains(&wildcard),
+      None => false,
+    }
+  }
+
+  fn commit(&mut self, text: &str) {
+    info!("context commits {}", text);
+
+    self.committed.push_str(text);
+    self.clear();
+  }
+
+  fn clear(&mut self) {
+    self.pinyin = None;
+    self.path.truncate(1);
+    self.input_sequence.clear();
+    self.cursor = 0;
+    self.overflow_number = 0;
+    self.reset_candidates();
+  }
+
+  // appends `ch` to the input, following the path or overflowing
+  fn push(&mut self, ch: char) {
+    self.input_sequence.push(ch);
+
+    if self.overflow_number > 0 {
+      self.overflow_number += 1
+    } else {
+      let next = self.step(ch);
+      if next.is_empty() {
+        self.overflow_number += 1;
+      } else {
+        self.path.push(next);
+      }
+    }
+  }
+
+  // matches `keys` from the root again, after editing inside the input
+  fn rebuild(&mut self, keys: Vec<char>) {
+    self.path.truncate(1);
+    self.input_sequence.clear();
+    self.overflow_number = 0;
+
+    for ch in keys {
+      self.push(ch);
+    }
+    self.reset_candidates();
+
+    info!(
+      "rebuild, input_seq: {:?}, cursor: {}, overflow: {}",
+      self.input_sequence, self.cursor, self.overflow_number
+    );
+  }
+
+  // back to the trigger key alone once the pinyin input is empty
+  fn restart_pinyin(&mut self, res: BackspaceResult) -> BackspaceResult {
+    if let BackspaceResult::Cancel = res {
+      self.pinyin = self.pinyin_engine.as_ref().map(|x| x.context());
     }
+
+    BackspaceResult::Candidates(self.candidates(0, CANDIDATE_BATCH), self.codes())
   }
 }
 
 struct QueueItem {
   node: NodeType,
   depth: usize,
+  // codes matched by the input
+  prefix: Arc<Vec<char>>,
   codes: Vec<char>,
 }
 
 #[derive(Debug)]
 struct FlattenItem {
   text: String,
-  depth: usize,
   codes: Vec<char>,
-  priority: u32,
-}
-
-impl cmp::PartialEq for FlattenItem {
-  fn eq(&self, rhs: &Self) -> bool {
-    self.text == rhs.text
-      && self.depth == rhs.depth
-      && self.codes == rhs.codes
-      && self.priority == rhs.priority
-  }
+  // the whole code, only if the input has wildcards
+  full_codes: Vec<char>,
 }
 
-impl cmp::PartialOrd for FlattenItem {
-  fn partial_cmp(&self, rhs: &Self) -> Option<cmp::Ordering> {
-    if self.depth != rhs.depth {
-      self.depth.partial_cmp(&rhs.depth)
-    } else {
-      rhs.priority.partial_cmp(&self.priority)
+impl CodeTableContext {
+  // restarts generating candidates for the current input
+  fn reset_candidates(&mut self) {
+    self.generated.clear();
+    self.frontier.clear();
+    self.taken = 0;
+
+    if !self.input_sequence.is_empty() && self.overflow_number == 0 {
+      self.frontier = self
+        .current()
+        .iter()
+        .map(|matched| QueueItem {
+          node: matched.node.clone(),
+          depth: 0,
+          prefix: matched.codes.clone(),
+          codes: Vec::new(),
+        })
+        .collect();
     }
   }
-}
 
-impl cmp::Eq for FlattenItem {}
-
-impl cmp::Ord for FlattenItem {
-  fn cmp(&self, other: &Self) -> cmp::Ordering {
-    self.partial_cmp(other).unwrap()
-  }
-}
+  // generates the next `count` candidates of the current level, which all
+  // rank after candidates of the previous levels, by learned frequency and
+  // priority. only the best values are kept while scanning the level, so a
+  // batch costs no more than its size beyond the scan
+  fn generate_level(&mut self, count: usize) {
+    let frequency = self.frequency.clone();
+    let frequency = frequency.as_ref().map(|x| x.read().unwrap());
+    let limit = self.taken + count;
+
+    // ranks of values as (frequency, priority, node, value), the worst of the
+    // best on top
+    let mut best: BinaryHeap<(Reverse<u32>, Reverse<u32>, usize, usize)> = BinaryHeap::new();
+    let mut total = 0;
+    for (i, item) in self.frontier.iter().enumerate() {
+      for (j, text) in item.node.values.iter().enumerate() {
+        total += 1;
+        best.push((
+          Reverse(frequency.as_ref().map_or(0, |x| x.get(&text.text))),
+          Reverse(text.priority),
+          i,
+          j,
+        ));
+        if best.len() > limit {
+          best.pop();
+        }
+      }
+    }
 
-impl CodeTableContext {
-  fn generate_candidates(&mut self) -> Vec<FlattenItem> {
-    if self.input_sequence.len() == 0 {
-      return vec![];
+    let has_wildcard = self.has_wildcard();
+    for (_, _, i, j) in best.into_sorted_vec().into_iter().skip(self.taken) {
+      let item = &self.frontier[i];
+
+      self.generated.push(FlattenItem {
+        text: item.node.values[j].text.clone(),
+        codes: item.codes.clone(),
+        full_codes: if has_wildcard {
+          item
+            .prefix
+            .iter()
+            .chain(item.codes.iter())
+            .cloned()
+            .collect()
+        } else {
+          Vec::new()
+        },
+      });
     }
 
-    let mut res: Vec<FlattenItem> = Vec::new();
+    self.taken = cmp::min(limit, total);
+    if self.taken == total {
+      self.next_level();
+    }
+  }
 
-    let mut queue: LinkedList<QueueItem> = LinkedList::new();
-    queue.push_back(QueueItem {
-      node: self.current.clone(),
-      depth: 0,
-      codes: Vec::new(),
-    });
+  // moves to the nodes one code longer
+  fn next_level(&mut self) {
+    let frontier = mem::take(&mut self.frontier);
+    self.taken = 0;
 
-    while queue.len() > 0 {
-      let mut item = queue.pop_front();
-      if let Some(front) = item.as_mut() {
-        let front_codes = front.codes.clone();
-
-        for text in &front.node.values {
-          res.push(FlattenItem {
-            text: text.text.clone(),
-            depth: front.depth,
-            codes: front_codes.clone(),
-            priority: text.priority,
-          });
-        }
+    for item in frontier {
+      // codes longer than max length can't be typed
+      if self.perfect_only || self.input_sequence.len() + item.depth >= self.options.max_length {
+        continue;
+      }
 
-        for (ch, child) in &front.node.children {
-          let mut codes = front_codes.clone();
-          codes.push(*ch);
+      for (ch, child) in &item.node.children {
+        let mut codes = item.codes.clone();
+        codes.push(*ch);
 
-          queue.push_back(QueueItem {
-            node: child.clone(),
-            depth: front.depth + 1,
-            codes: codes,
-          });
-        }
+        self.frontier.push(QueueItem {
+          node: child.clone(),
+          depth: item.depth + 1,
+          prefix: item.prefix.clone(),
+          codes: codes,
+        });
       }
     }
+  }
 
-    res
+  // generates until there are `count` candidates or no more
+  fn generate(&mut self, count: usize) {
+    while self.generated.len() < count && !self.frontier.is_empty() {
+      self.generate_level(count - self.generated.len());
+    }
   }
 }
 
 impl InputContext for CodeTableContext {
   fn feed(&mut self, ch: char) -> (Vec<Candidate>, Vec<String>) {
-    self.input_sequence.push(ch);
+    if let Some(pinyin) = &mut self.pinyin {
+      pinyin.feed(ch);
 
-    if self.overflow_number > 0 {
-      self.overflow_number += 1
-    } else {
-      self.current = match self.current.child(&ch) {
-        Some(child) => child,
-        None => {
-          self.overflow_number += 1;
+      return (self.candidates(0, CANDIDATE_BATCH), self.codes());
+    }
 
-          self.current.clone()
-        }
-      };
+    if self.input_sequence.is_empty() && Some(ch) == self.options.pinyin_trigger {
+      if let Some(engine) = &self.pinyin_engine {
+        info!("feed {}, start pinyin lookup", ch);
+        self.pinyin = Some(engine.context());
+
+        return (vec![], self.codes());
+      }
+    }
+
+    let full = self.input_sequence.len() >= self.options.max_length;
+
+    // typing inside the input neither commits nor clears it
+    if self.cursor < self.input_sequence.len() {
+      if !full {
+        let mut keys = self.input_sequence.clone();
+        keys.insert(self.cursor, ch);
+        self.cursor += 1;
+        self.rebuild(keys);
+      }
+
+      return (self.candidates(0, CANDIDATE_BATCH), self.codes());
     }
 
+    if self.options.top_commit
+      && self.overflow_number == 0
+      && !self.input_sequence.is_empty()
+      && (full || self.step(ch).is_empty())
+    {
+      if let Some(first) = self.generated.first() {
+        let text = first.text.clone();
+        self.commit(&text);
+      }
+    }
+
+    if self.input_sequence.len() >= self.options.max_length {
+      info!("feed {}, code reaches max length, ignored", ch);
+
+      return (self.candidates(0, CANDIDATE_BATCH), self.codes());
+    }
+
+    self.push(ch);
+    self.cursor = self.input_sequence.len();
+
     info!(
       "feed {}, input_seq: {:?}, overflow: {}",
       ch, self.input_sequence, self.overflow_number
     );
+    self.reset_candidates();
 
     if self.overflow_number > 0 {
-      (vec![], self.codes())
-    } else {
-      let mut candidates = self.generate_candidates();
-      candidates.sort();
+      if self.options.empty_code == EmptyCode::Clear {
+        self.clear();
+      }
+
+      return (vec![], self.codes());
+    }
 
-      (
-        candidates
-          .iter()
-          .map(|item| Candidate::new(item.text.clone(), item.codes.clone()))
-          .collect(),
-        self.codes(),
-      )
+    let candidates = self.candidates(0, CANDIDATE_BATCH);
+    let auto_commit = match self.options.commit_mode {
+      CommitMode::Manual => None,
+      CommitMode::AutoOnUnique if candidates.len() == 1 => Some(candidates[0].text.clone()),
+      CommitMode::AutoOnUnique => None,
+      CommitMode::AutoOnMaxLength if self.input_sequence.len() >= self.options.max_length => {
+        let mut perfect = candidates.iter().filter(|x| x.remain_codes.is_empty());
+
+        match (perfect.next(), perfect.next()) {
+          (Some(only), None) => Some(only.text.clone()),
+          _ => None,
+        }
+      }
+      CommitMode::AutoOnMaxLength => None,
+    };
+
+    if let Some(text) = auto_commit {
+      self.commit(&text);
+
+      return (vec![], self.codes());
     }
+
+    (candidates, self.codes())
   }
 
   fn backspace(&mut self) -> BackspaceResult {
+    if let Some(pinyin) = &mut self.pinyin {
+      // only the trigger key is left
+      if pinyin.codes().is_empty() {
+        self.pinyin = None;
+        return BackspaceResult::Cancel;
+      }
+
+      let res = pinyin.backspace();
+      return self.restart_pinyin(res);
+    }
+
+    if self.cursor < self.input_sequence.len() {
+      if self.cursor > 0 {
+        let mut keys = self.input_sequence.clone();
+        self.cursor -= 1;
+        keys.remove(self.cursor);
+        self.rebuild(keys);
+      }
+
+      return BackspaceResult::Candidates(self.candidates(0, CANDIDATE_BATCH), self.codes());
+    }
+
     self.input_sequence.pop();
-    if self.input_sequence.len() == 0 {
+    self.cursor = self.input_sequence.len();
+    if self.input_sequence.is_empty() {
       return BackspaceResult::Cancel;
     }
 
@@ -173,40 +485,116 @@ impl InputContext for CodeTableContext {
       self.input_sequence, self.overflow_number
     );
 
-    if self.overflow_number == 0 {
-      if !this_round {
-        let father: Option<NodeType>;
-
-        if self.current.father.is_some() {
-          match self.current.father.as_ref().unwrap().upgrade() {
-            Some(f) => {
-              father = Some(f.clone());
-            }
-            None => return BackspaceResult::Candidates(vec![], self.codes()),
-          }
-
-          if let Some(father) = father {
-            self.current = father;
-          }
-        } else {
-          return BackspaceResult::Candidates(vec![], self.codes());
-        }
+    if self.overflow_number == 0 && !this_round {
+      self.path.pop();
+    }
+    self.reset_candidates();
+
+    let candidates = self.candidates(0, CANDIDATE_BATCH);
+    info!("backspaced candidates: {:?}", candidates);
+
+    BackspaceResult::Candidates(candidates, self.codes())
+  }
+
+  fn delete(&mut self) -> BackspaceResult {
+    if let Some(pinyin) = &mut self.pinyin {
+      let res = pinyin.delete();
+      return self.restart_pinyin(res);
+    }
+
+    if self.cursor < self.input_sequence.len() {
+      let mut keys = self.input_sequence.clone();
+      keys.remove(self.cursor);
+      if keys.is_empty() {
+        self.clear();
+        return BackspaceResult::Cancel;
       }
+      self.rebuild(keys);
+    }
 
-      let mut candidates = self.generate_candidates();
-      candidates.sort();
-
-      info!("backspaced candidates: {:?}", candidates);
-      BackspaceResult::Candidates(
-        candidates
-          .iter()
-          .map(|item| Candidate::new(item.text.clone(), item.codes.clone()))
-          .collect(),
-        self.codes(),
-      )
-    } else {
-      BackspaceResult::Candidates(vec![], self.codes())
+    BackspaceResult::Candidates(self.candidates(0, CANDIDATE_BATCH), self.codes())
+  }
+
+  fn move_cursor(&mut self, motion: CursorMotion) {
+    if let Some(pinyin) = &mut self.pinyin {
+      return pinyin.move_cursor(motion);
     }
+
+    self.cursor = match motion {
+      CursorMotion::Left => self.cursor.saturating_sub(1),
+      CursorMotion::Right => cmp::min(self.cursor + 1, self.input_sequence.len()),
+      CursorMotion::Start => 0,
+      CursorMotion::End => self.input_sequence.len(),
+    };
+  }
+
+  fn cursor(&self) -> usize {
+    match &self.pinyin {
+      // after the trigger key
+      Some(pinyin) => pinyin.cursor() + 1,
+      None => self.cursor,
+    }
+  }
+
+  fn confirm(&mut self, index: usize) -> Option<ConfirmResult> {
+    if let Some(pinyin) = &mut self.pinyin {
+      // the rest of the pinyin input stays in the lookup
+      return match pinyin.confirm(index)? {
+        ConfirmResult::Partial(text, ..) => Some(ConfirmResult::Partial(
+          text,
+          self.candidates(0, CANDIDATE_BATCH),
+          self.codes(),
+        )),
+        done => Some(done),
+      };
+    }
+
+    self
+      .candidates(index, 1)
+      .pop()
+      .map(|candidate| ConfirmResult::Done(candidate.text))
+  }
+
+  fn candidates(&mut self, page: usize, per_page: usize) -> Vec<Candidate> {
+    // pinyin candidates teach their codes in the table
+    if let Some(pinyin) = &mut self.pinyin {
+      let reverse = self.reverse.read().unwrap();
+
+      return pinyin
+        .candidates(page, per_page)
+        .into_iter()
+        .map(|candidate| {
+          let codes = reverse
+            .get(&candidate.text)
+            .and_then(|codes| codes.first())
+            .map_or_else(Vec::new, |code| code.chars().collect());
+
+          candidate.with_codes(codes)
+        })
+        .collect();
+    }
+
+    self.generate((page + 1) * per_page);
+
+    let end = cmp::min((page + 1) * per_page, self.generated.len());
+    let start = cmp::min(page * per_page, end);
+
+    self.generated[start..end]
+      .iter()
+      .map(|item| {
+        Candidate::new(item.text.clone(), item.codes.clone()).with_codes(item.full_codes.clone())
+      })
+      .collect()
+  }
+
+  fn has_more(&mut self, page: usize, per_page: usize) -> bool {
+    if let Some(pinyin) = &mut self.pinyin {
+      return pinyin.has_more(page, per_page);
+    }
+
+    self.generate((page + 1) * per_page + 1);
+
+    self.generated.len() > (page + 1) * per_page
   }
 
   fn id(&self) -> ContextId {
@@ -214,6 +602,17 @@ impl InputContext for CodeTableContext {
   }
 
   fn codes(&self) -> Vec<String> {
+    if let Some(pinyin) = &self.pinyin {
+      let mut res = pinyin.codes();
+      let trigger = self.options.pinyin_trigger.unwrap_or_default().to_string();
+
+      match res.first_mut() {
+        Some(first) => first.insert_str(0, &trigger),
+        None => res.push(trigger),
+      }
+      return res;
+    }
+
     vec![self
       .input_sequence
       .iter()
@@ -221,4 +620,236 @@ impl InputContext for CodeTableContext {
       .collect::<Vec<String>>()
       .join("")]
   }
+
+  fn take_commit(&mut self) -> Option<String> {
+    if self.committed.is_empty() {
+      None
+    } else {
+      Some(std::mem::take(&mut self.committed))
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::data::TrieBuilder;
+  use crate::engine::pinyin::{FuzzySyllables, PinyinDictionary, Segmenter, WordEntry};
+
+  fn context(options: ContextOptions) -> CodeTableContext {
+    let mut builder = TrieBuilder::new();
+    for (code, text, priority) in &[
+      ("vs", "中", 100),
+      ("vsll", "中", 100),
+      ("vsg", "终", 100),
+      ("vsgo", "中国", 100),
+      ("vsgo", "重构", 200),
+      ("go", "国", 100),
+      ("gr", "个人", 100),
+    ] {
+      builder.insert(
+        code.chars().collect::<Vec<char>>().iter(),
+        ResultText {
+          text: text.to_string(),
+          priority: *priority,
+        },
+      );
+    }
+
+    CodeTableContext::new(
+      builder.build().root(),
+      false,
+      None,
+      ContextOptions {
+        max_length: 4,
+        ..options
+      },
+      None,
+      Arc::new(RwLock::new(HashMap::new())),
+    )
+  }
+
+  fn feed(ctx: &mut CodeTableContext, keys: &str) -> Vec<String> {
+    let mut res = vec![];
+    for ch in keys.chars() {
+      res = ctx.feed(ch).0.into_iter().map(|x| x.text).collect();
+    }
+    res
+  }
+
+  #[test]
+  fn shorter_codes_and_higher_priority_first() {
+    let mut ctx = context(ContextOptions::default());
+
+    let candidates = feed(&mut ctx, "vs");
+    assert_eq!(candidates[..3], ["中", "终", "重构"]);
+    assert_eq!(candidates.len(), 5);
+    assert_eq!(feed(&mut ctx, "go"), vec!["重构", "中国"]);
+  }
+
+  #[test]
+  fn generated_by_batches() {
+    let mut ctx = context(ContextOptions::default());
+    feed(&mut ctx, "vs");
+    ctx.reset_candidates();
+
+    let texts = |candidates: Vec<Candidate>| -> Vec<String> {
+      candidates.into_iter().map(|x| x.text).collect()
+    };
+    assert_eq!(texts(ctx.candidates(0, 3)), vec!["中", "终", "重构"]);
+    // the rest of the level waits until asked for
+    assert_eq!(ctx.generated.len(), 3);
+    assert_eq!(ctx.taken, 1);
+
+    let mut rest = texts(ctx.candidates(1, 3));
+    rest.sort();
+    assert_eq!(rest, vec!["中", "中国"]);
+    assert!(!ctx.has_more(1, 3));
+  }
+
+  #[test]
+  fn wildcard() {
+    let mut ctx = context(ContextOptions {
+      wildcard: Some('?'),
+      ..ContextOptions::default()
+    });
+    feed(&mut ctx, "?o");
+    let candidates = ctx.candidates(0, 10);
+
+    assert_eq!(candidates[0].text, "国");
+    assert_eq!(candidates[0].codes, vec!['g', 'o']);
+  }
+
+  #[test]
+  fn top_commit() {
+    let mut ctx = context(ContextOptions {
+      top_commit: true,
+      ..ContextOptions::default()
+    });
+
+    assert_eq!(feed(&mut ctx, "vsgog").len(), 2);
+    assert_eq!(ctx.take_commit(), Some("重构".to_string()));
+    assert_eq!(ctx.codes(), vec!["g"]);
+  }
+
+  #[test]
+  fn commit_modes() {
+    let mut ctx = context(ContextOptions {
+      commit_mode: CommitMode::AutoOnUnique,
+      ..ContextOptions::default()
+    });
+    assert!(feed(&mut ctx, "gr").is_empty());
+    assert_eq!(ctx.take_commit(), Some("个人".to_string()));
+
+    let mut ctx = context(ContextOptions {
+      commit_mode: CommitMode::AutoOnMaxLength,
+      ..ContextOptions::default()
+    });
+    assert_eq!(feed(&mut ctx, "vsgo").len(), 2);
+    assert_eq!(ctx.take_commit(), None);
+
+    let mut ctx = context(ContextOptions {
+      commit_mode: CommitMode::AutoOnMaxLength,
+      ..ContextOptions::default()
+    });
+    assert!(feed(&mut ctx, "vsll").is_empty());
+    assert_eq!(ctx.take_commit(), Some("中".to_string()));
+  }
+
+  #[test]
+  fn empty_code() {
+    let mut ctx = context(ContextOptions {
+      empty_code: EmptyCode::Keep,
+      ..ContextOptions::default()
+    });
+    assert!(feed(&mut ctx, "vx").is_empty());
+    assert_eq!(ctx.codes(), vec!["vx"]);
+
+    let mut ctx = context(ContextOptions {
+      empty_code: EmptyCode::Clear,
+      ..ContextOptions::default()
+    });
+    assert!(feed(&mut ctx, "vx").is_empty());
+    assert_eq!(ctx.codes(), vec![""]);
+  }
+
+  #[test]
+  fn shared_options_keep_every_key() {
+    let mut ctx = context(ContextOptions {
+      commit_mode: CommitMode::AutoOnUnique,
+      empty_code: EmptyCode::Clear,
+      top_commit: true,
+      ..ContextOptions::default()
+    });
+    ctx.options = ctx.options.shared();
+
+    feed(&mut ctx, "grvsllx");
+    assert_eq!(ctx.take_commit(), None);
+    assert_eq!(ctx.codes(), vec!["grvsllx"]);
+  }
+
+  fn pinyin_engine() -> Arc<PinyinEngine> {
+    let segmenter = Segmenter::new(None, FuzzySyllables::new(vec![]).unwrap(), vec![]);
+    let mut dictionary = PinyinDictionary::new();
+    for (text, pinyin) in &[("中", "zhong"), ("钟", "zhong"), ("国", "guo")] {
+      dictionary.insert(
+        &[segmenter.table().parse(pinyin).unwrap()],
+        WordEntry {
+          text: text.to_string(),
+          priority: 100,
+          tones: vec![None],
+        },
+      );
+    }
+
+    Arc::new(PinyinEngine::dictionary(dictionary, segmenter, None))
+  }
+
+  #[test]
+  fn pinyin_lookup() {
+    let mut ctx = context(ContextOptions {
+      pinyin_trigger: Some('`'),
+      ..ContextOptions::default()
+    });
+    ctx.pinyin_engine = Some(pinyin_engine());
+    ctx
+      .reverse
+      .write()
+      .unwrap()
+      .insert("中".to_string(), vec!["vsll".to_string(), "vs".to_string()]);
+
+    assert!(feed(&mut ctx, "`").is_empty());
+    assert_eq!(ctx.codes(), vec!["`"]);
+    let candidates = ctx.feed('z').0;
+    assert_eq!(ctx.codes(), vec!["`z"]);
+    assert!(candidates.iter().all(|x| x.text != "国"));
+
+    feed(&mut ctx, "hongguo");
+    assert_eq!(ctx.codes(), vec!["`zhong", "guo"]);
+    // in the shown codes, after the trigger key
+    assert_eq!(ctx.cursor(), 10);
+    // the longest code of each character is shown
+    let candidates = ctx.candidates(0, 10);
+    let zhong = candidates.iter().find(|x| x.text == "中").unwrap();
+    assert_eq!(zhong.codes, vec!['v', 's', 'l', 'l']);
+
+    // the rest of the input stays in the lookup
+    let index = candidates.iter().position(|x| x.text == "钟").unwrap();
+    match ctx.confirm(index) {
+      Some(ConfirmResult::Partial(text, candidates, codes)) => {
+        assert_eq!(text, "钟");
+        assert_eq!(candidates[0].text, "国");
+        assert_eq!(codes, vec!["`guo"]);
+      }
+      res => panic!("unexpected {:?}", res),
+    }
+
+    for _ in 0..3 {
+      ctx.backspace();
+    }
+    assert_eq!(ctx.codes(), vec!["`"]);
+    assert!(matches!(ctx.backspace(), BackspaceResult::Cancel));
+    assert!(ctx.pinyin.is_none());
+    assert_eq!(feed(&mut ctx, "vs")[0], "中");
+  }
 }
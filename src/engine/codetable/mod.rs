@@ -1,5 +1,8 @@
 pub mod code_table;
+pub mod compiled;
 pub mod input_context;
+pub mod phrase_rules;
+pub mod user_table;
 
 pub use code_table::CodeTable;
-pub use input_context::CodeTableContext;
+pub use input_context::{CodeTableContext, CommitMode, ContextOptions, EmptyCode};
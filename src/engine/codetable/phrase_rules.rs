@@ -0,0 +1,188 @@
+// rules of 五笔 and 小鹤音形
+static DEFAULT_RULES: &[(&str, &str)] = &[("2", "AaAbBaBb"), ("3", "AaBaCaCb"), ("4+", "AaBaCaZa")];
+
+// an index counting from the start or the end
+#[derive(Clone, Copy, Debug)]
+enum Pick {
+  Start(usize),
+  End(usize),
+}
+
+impl Pick {
+  // `a` to `t` count from the start, `u` to `z` from the end
+  fn parse(ch: char) -> Option<Pick> {
+    match ch {
+      'a'..='t' => Some(Pick::Start(ch as usize - 'a' as usize)),
+      'u'..='z' => Some(Pick::End('z' as usize - ch as usize)),
+      _ => None,
+    }
+  }
+
+  fn index(&self, len: usize) -> Option<usize> {
+    match *self {
+      Pick::Start(i) if i < len => Some(i),
+      Pick::End(i) if i < len => Some(len - 1 - i),
+      _ => None,
+    }
+  }
+}
+
+struct PhraseRule {
+  length: usize,
+  // if the rule encodes longer phrases as well
+  longer: bool,
+  // character and code of each key
+  formula: Vec<(Pick, Pick)>,
+}
+
+/// Rules encoding a phrase from the codes of its characters, one formula per
+/// phrase length, e.g. `{"2": "AaAbBaBb", "3": "AaBaCaCb", "4+": "AaBaCaZa"}`.
+///
+/// Each pair of a formula picks a character by an upper case letter and one
+/// of its codes by a lower case letter, `A` to `T` counting from the start
+/// and `U` to `Z` from the end, so `Za` is the first code of the last
+/// character. A length ending with `+` covers longer phrases too.
+pub struct PhraseRules {
+  rules: Vec<PhraseRule>,
+}
+
+impl Default for PhraseRules {
+  fn default() -> PhraseRules {
+    PhraseRules::parse(DEFAULT_RULES.iter().cloned()).expect("invalid default phrase rules")
+  }
+}
+
+impl PhraseRules {
+  pub fn parse<'a>(rules: impl Iterator<Item = (&'a str, &'a str)>) -> Result<PhraseRules, String> {
+    let mut res = PhraseRules { rules: Vec::new() };
+
+    for (length, formula) in rules {
+      let longer = length.ends_with('+');
+      let length = length
+        .trim_end_matches('+')
+        .parse::<usize>()
+        .ok()
+        .filter(|x| *x >= 2)
+        .ok_or_else(|| format!("invalid phrase length '{}'", length))?;
+
+      let error = || format!("invalid formula '{}' of {} characters", formula, length);
+      let chars: Vec<char> = formula.chars().collect();
+      if chars.is_empty() || chars.len() % 2 != 0 {
+        return Err(error());
+      }
+
+      let mut picks = Vec::new();
+      for pair in chars.chunks(2) {
+        if !pair[0].is_ascii_uppercase() {
+          return Err(error());
+        }
+        let character = Pick::parse(pair[0].to_ascii_lowercase())
+          .filter(|x| x.index(length).is_some())
+          .ok_or_else(error)?;
+        let code = Pick::parse(pair[1]).ok_or_else(error)?;
+        picks.push((character, code));
+      }
+
+      if res.rules.iter().any(|x| x.length == length) {
+        return Err(format!("duplicate rules of {} characters", length));
+      }
+      res.rules.push(PhraseRule {
+        length,
+        longer,
+        formula: picks,
+      });
+    }
+    res.rules.sort_by_key(|x| x.length);
+
+    Ok(res)
+  }
+
+  fn rule(&self, length: usize) -> Option<&PhraseRule> {
+    self
+      .rules
+      .iter()
+      .rev()
+      .find(|x| x.length == length || (x.longer && x.length < length))
+  }
+
+  /// Length of the longest phrase encoded by a rule of its own length.
+  pub fn max_length(&self) -> usize {
+    self.rules.iter().map(|x| x.length).max().unwrap_or(0)
+  }
+
+  /// Encodes a phrase from the full codes of its characters, codes too short
+  /// for the formula are taken as they are.
+  pub fn encode(&self, codes: &[&str]) -> Result<String, String> {
+    if codes.len() < 2 {
+      return Err("phrase should have at least 2 characters".to_string());
+    }
+    let rule = self
+      .rule(codes.len())
+      .ok_or_else(|| format!("no rule encodes phrases of {} characters", codes.len()))?;
+
+    let mut res = String::new();
+    for (character, code) in &rule.formula {
+      let keys: Vec<char> = codes[character.index(codes.len()).unwrap()]
+        .chars()
+        .collect();
+
+      if let Some(i) = code.index(keys.len()) {
+        res.push(keys[i]);
+      }
+    }
+
+    Ok(res)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn default_rules() {
+    let rules = PhraseRules::default();
+
+    assert_eq!(rules.encode(&["vsll", "goll"]).unwrap(), "vsgo");
+    assert_eq!(rules.encode(&["vsll", "goll", "rfrr"]).unwrap(), "vgrf");
+    assert_eq!(
+      rules.encode(&["vsll", "goll", "rfrr", "mbmm"]).unwrap(),
+      "vgrm"
+    );
+    assert_eq!(
+      rules
+        .encode(&["vsll", "goll", "rfrr", "mbmm", "ab"])
+        .unwrap(),
+      "vgra"
+    );
+    assert!(rules.encode(&["vsll"]).is_err());
+    assert_eq!(rules.max_length(), 4);
+  }
+
+  #[test]
+  fn short_codes() {
+    let rules = PhraseRules::default();
+
+    assert_eq!(rules.encode(&["v", "go"]).unwrap(), "vgo");
+  }
+
+  #[test]
+  fn custom_rules() {
+    let rules = PhraseRules::parse(vec![("2", "AaAzBaBz"), ("3", "AaBaZa")].into_iter()).unwrap();
+
+    assert_eq!(rules.encode(&["abc", "def"]).unwrap(), "acdf");
+    assert_eq!(rules.encode(&["abc", "def", "ghi"]).unwrap(), "adg");
+    assert!(rules.encode(&["abc", "def", "ghi", "jkl"]).is_err());
+    assert_eq!(rules.max_length(), 3);
+  }
+
+  #[test]
+  fn invalid_rules() {
+    assert!(PhraseRules::parse(vec![("1", "AaAb")].into_iter()).is_err());
+    assert!(PhraseRules::parse(vec![("x", "AaAb")].into_iter()).is_err());
+    assert!(PhraseRules::parse(vec![("2", "AaA")].into_iter()).is_err());
+    assert!(PhraseRules::parse(vec![("2", "aaBb")].into_iter()).is_err());
+    assert!(PhraseRules::parse(vec![("2", "AaCa")].into_iter()).is_err());
+    assert!(PhraseRules::parse(vec![("2", "AaBa"), ("2+", "AaBa")].into_iter()).is_err());
+  }
+}
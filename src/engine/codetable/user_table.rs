@@ -0,0 +1,170 @@
+use crate::path::LocalDataPath;
+use log::warn;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::PathBuf;
+
+/// A phrase created by the user, or from characters the user commits one by
+/// one.
+#[derive(Clone, Debug)]
+pub struct UserPhrase {
+  pub text: String,
+  pub code: String,
+  pub auto: bool,
+}
+
+/// Phrases created by the user, persisted as `text<TAB>code[<TAB>auto]` lines
+/// under the user data directory, apart from the system table.
+pub struct UserTable {
+  filepath: PathBuf,
+  phrases: Vec<UserPhrase>,
+}
+
+impl UserTable {
+  pub fn load(filename: &str) -> UserTable {
+    UserTable::load_path(LocalDataPath::new().sub("user").file(filename))
+  }
+
+  /// Loads user phrases from `filepath`, starting empty if the file doesn't
+  /// exist yet.
+  pub fn load_path(filepath: PathBuf) -> UserTable {
+    let mut res = UserTable {
+      filepath,
+      phrases: Vec::new(),
+    };
+
+    let file = match File::open(&res.filepath) {
+      Ok(file) => file,
+      Err(_) => return res,
+    };
+
+    for l in BufReader::new(file).lines() {
+      if let Ok(line) = l {
+        let v: Vec<&str> = line.trim().split('\t').collect();
+
+        if v.len() == 2 || (v.len() == 3 && v[2] == "auto") {
+          res.phrases.push(UserPhrase {
+            text: v[0].to_string(),
+            code: v[1].to_string(),
+            auto: v.len() == 3,
+          });
+        } else {
+          warn!("skip '{}' in {}", line, res.filepath.display());
+        }
+      }
+    }
+
+    res
+  }
+
+  pub fn phrases(&self) -> &[UserPhrase] {
+    &self.phrases
+  }
+
+  pub fn contains(&self, text: &str, code: &str) -> bool {
+    self
+      .phrases
+      .iter()
+      .any(|x| x.text == text && x.code == code)
+  }
+
+  /// Codes of the user phrase `text`.
+  pub fn codes(&self, text: &str) -> Vec<String> {
+    self
+      .phrases
+      .iter()
+      .filter(|x| x.text == text)
+      .map(|x| x.code.clone())
+      .collect()
+  }
+
+  // the phrases are changed only once they are saved
+  pub fn add(&mut self, text: &str, code: &str, auto: bool) -> Result<(), String> {
+    let mut phrases = self.phrases.clone();
+    phrases.push(UserPhrase {
+      text: text.to_string(),
+      code: code.to_string(),
+      auto,
+    });
+    self.save(&phrases)?;
+    self.phrases = phrases;
+    Ok(())
+  }
+
+  /// Removes the user phrase `text` of each of `codes`.
+  pub fn remove(&mut self, text: &str, codes: &[String]) -> Result<(), String> {
+    let phrases: Vec<UserPhrase> = self
+      .phrases
+      .iter()
+      .filter(|x| x.text != text || !codes.contains(&x.code))
+      .cloned()
+      .collect();
+    self.save(&phrases)?;
+    self.phrases = phrases;
+    Ok(())
+  }
+
+  // written aside and renamed over the file, so a failure keeps the old one
+  fn save(&self, phrases: &[UserPhrase]) -> Result<(), String> {
+    let error = |e: std::io::Error| format!("failed to save {}: {}", self.filepath.display(), e);
+
+    if let Some(dir) = self.filepath.parent() {
+      fs::create_dir_all(dir).map_err(error)?;
+    }
+
+    let mut tmp = self.filepath.clone().into_os_string();
+    tmp.push(".tmp");
+    {
+      let mut writer = BufWriter::new(File::create(&tmp).map_err(error)?);
+      for phrase in phrases {
+        if phrase.auto {
+          writeln!(writer, "{}\t{}\tauto", phrase.text, phrase.code).map_err(error)?;
+        } else {
+          writeln!(writer, "{}\t{}", phrase.text, phrase.code).map_err(error)?;
+        }
+      }
+      writer.flush().map_err(error)?;
+    }
+
+    fs::rename(&tmp, &self.filepath).map_err(error)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn filepath(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("ime-neovim-user-{}-{}", name, std::process::id()))
+  }
+
+  #[test]
+  fn saved_and_loaded() {
+    let path = filepath("save");
+    let mut table = UserTable::load_path(path.clone());
+    table.add("中国", "vsgo", false).unwrap();
+    table.add("中国", "vsg", true).unwrap();
+    table.add("人民", "rfmb", true).unwrap();
+    table
+      .remove("中国", &["vsgo".to_string(), "vsg".to_string()])
+      .unwrap();
+
+    let table = UserTable::load_path(path.clone());
+    fs::remove_file(&path).unwrap();
+    assert_eq!(table.codes("中国"), Vec::<String>::new());
+    assert_eq!(table.codes("人民"), vec!["rfmb"]);
+    assert!(table.phrases()[0].auto);
+  }
+
+  #[test]
+  fn failed_save_keeps_phrases() {
+    // no directory can be created under a file
+    let file = filepath("file");
+    fs::write(&file, "").unwrap();
+    let mut table = UserTable::load_path(file.join("phrases"));
+
+    assert!(table.add("中国", "vsgo", false).is_err());
+    fs::remove_file(&file).unwrap();
+    assert!(table.phrases().is_empty());
+  }
+}
@@ -31,14 +31,73 @@ pub enum BackspaceResult {
   Cancel,
 }
 
+#[derive(Debug, Clone, Copy)]
+pub enum CursorMotion {
+  Left,
+  Right,
+  Start,
+  End,
+}
+
+#[derive(Debug)]
+pub enum ConfirmResult {
+  // the candidate covers the whole input
+  Done(String),
+  // the candidate covers the leading part of the input, the rest is left
+  // with its candidates and codes
+  Partial(String, Vec<Candidate>, Vec<String>),
+}
+
+/// Candidates an input context returns on each key, the rest are generated
+/// by pages.
+pub static CANDIDATE_BATCH: usize = 32;
+
 pub trait InputContext: Send {
   fn feed(&mut self, ch: char) -> (Vec<Candidate>, Vec<String>);
 
+  /// Candidates on `page` of the current input, `per_page` candidates a
+  /// page.
+  fn candidates(&mut self, page: usize, per_page: usize) -> Vec<Candidate>;
+
+  /// If there are candidates after `page`.
+  fn has_more(&mut self, page: usize, per_page: usize) -> bool;
+
+  /// Deletes the key before the cursor.
   fn backspace(&mut self) -> BackspaceResult;
 
+  /// Deletes the key after the cursor.
+  fn delete(&mut self) -> BackspaceResult;
+
+  fn move_cursor(&mut self, motion: CursorMotion);
+
+  /// Position of the cursor in the preedit, `codes()` joined by `'`, in
+  /// characters.
+  fn cursor(&self) -> usize;
+
+  /// Confirms the `index`th candidate of the current input, `None` if there
+  /// is no such candidate.
+  fn confirm(&mut self, index: usize) -> Option<ConfirmResult> {
+    self
+      .candidates(index, 1)
+      .pop()
+      .map(|candidate| ConfirmResult::Done(candidate.text))
+  }
+
   fn id(&self) -> ContextId;
 
   fn codes(&self) -> Vec<String>;
+
+  /// Takes the text the context committed on its own while feeding, e.g.
+  /// auto-commit of a unique full-length code.
+  fn take_commit(&mut self) -> Option<String> {
+    None
+  }
+
+  /// If `key`, one of the tone keys of the engine, sets a tone at the cursor
+  /// rather than selects a candidate.
+  fn accepts_tone(&self, _key: char) -> bool {
+    false
+  }
 }
 
 pub trait IMEngine: Send {
@@ -46,5 +105,46 @@ pub trait IMEngine: Send {
 
   fn start_context_async(&self) -> Arc<Mutex<dyn InputContext>>;
 
+  /// Starts a context owned by the caller, e.g. a mixed context.
+  fn new_context(&self) -> Box<dyn InputContext>;
+
+  /// Starts a context fed along with others by a mixed engine, which must
+  /// keep every key it is fed.
+  fn shared_context(&self) -> Box<dyn InputContext> {
+    self.new_context()
+  }
+
   fn keycodes(&self) -> HashSet<char>;
+
+  /// Keys typing tones after a syllable, not starting an input.
+  fn tone_keys(&self) -> HashSet<char> {
+    HashSet::new()
+  }
+
+  /// Keys separating syllables in an input, not starting one.
+  fn separators(&self) -> HashSet<char> {
+    HashSet::new()
+  }
+
+  /// Called with the text of each confirmed candidate.
+  fn commit(&mut self, _text: &str) {}
+
+  /// Writes what the engine learned and hasn't saved yet.
+  fn flush(&mut self) {}
+
+  /// Adds a user phrase, encoded by the engine if `code` is not given.
+  /// Returns the code of the phrase.
+  fn add_phrase(&mut self, _text: &str, _code: Option<&str>) -> Result<String, String> {
+    Err("user phrases are not supported by this engine".to_string())
+  }
+
+  /// Codes of `text`, longest first.
+  fn reverse_lookup(&self, _text: &str) -> Vec<String> {
+    vec![]
+  }
+
+  /// Deletes a user phrase, under every code of it if `code` is not given.
+  fn delete_phrase(&mut self, _text: &str, _code: Option<&str>) -> Result<(), String> {
+    Err("user phrases are not supported by this engine".to_string())
+  }
 }
@@ -0,0 +1,198 @@
+use crate::path::LocalDataPath;
+use log::warn;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, OnceLock, RwLock, Weak};
+use std::time::{Duration, Instant};
+
+// learned counts are written after this many commits, or once this long has
+// passed since the last write
+static SAVE_COMMITS: u32 = 20;
+static SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Times each text has been confirmed, persisted as `text<TAB>count` lines
+/// under the user data directory. Counts are learned in memory and written
+/// from time to time, and when dropped.
+pub struct WordFrequency {
+  filepath: PathBuf,
+  counts: HashMap<String, u32>,
+  // commits not written yet, and when the file was last written
+  unsaved: u32,
+  last_save: Instant,
+}
+
+impl WordFrequency {
+  /// Loads learned frequencies from `filename`, starting empty if the file
+  /// doesn't exist yet. Engines learning into the same file share the
+  /// counts, so one doesn't overwrite what the other learned.
+  pub fn shared(filename: &str) -> Arc<RwLock<WordFrequency>> {
+    WordFrequency::shared_path(LocalDataPath::new().sub("user").file(filename))
+  }
+
+  fn shared_path(filepath: PathBuf) -> Arc<RwLock<WordFrequency>> {
+    static LOADED: OnceLock<Mutex<HashMap<PathBuf, Weak<RwLock<WordFrequency>>>>> = OnceLock::new();
+    let mut loaded = LOADED.get_or_init(Default::default).lock().unwrap();
+
+    if let Some(res) = loaded.get(&filepath).and_then(Weak::upgrade) {
+      return res;
+    }
+    loaded.retain(|_, x| x.strong_count() > 0);
+
+    let res = Arc::new(RwLock::new(WordFrequency::load_path(filepath.clone())));
+    loaded.insert(filepath, Arc::downgrade(&res));
+    res
+  }
+
+  fn load_path(filepath: PathBuf) -> WordFrequency {
+    let mut res = WordFrequency {
+      filepath,
+      counts: HashMap::new(),
+      unsaved: 0,
+      last_save: Instant::now(),
+    };
+
+    let file = match File::open(&res.filepath) {
+      Ok(file) => file,
+      Err(_) => return res,
+    };
+
+    for l in BufReader::new(file).lines() {
+      if let Ok(line) = l {
+        let v: Vec<&str> = line.trim().split('\t').collect();
+
+        match (v.len(), v.last().map(|x| x.parse::<u32>())) {
+          (2, Some(Ok(count))) => {
+            res.counts.insert(v[0].to_string(), count);
+          }
+          _ => warn!("skip '{}' in {}", line, res.filepath.display()),
+        }
+      }
+    }
+
+    res
+  }
+
+  pub fn get(&self, text: &str) -> u32 {
+    self.counts.get(text).cloned().unwrap_or(0)
+  }
+
+  pub fn bump(&mut self, text: &str) {
+    *self.counts.entry(text.to_string()).or_insert(0) += 1;
+  }
+
+  /// Bumps `text`, persisting the counts once enough commits or time have
+  /// passed.
+  pub fn learn(&mut self, text: &str) {
+    self.bump(text);
+    self.unsaved += 1;
+
+    if self.unsaved >= SAVE_COMMITS || self.last_save.elapsed() >= SAVE_INTERVAL {
+      self.flush();
+    }
+  }
+
+  /// Writes the counts if any commit hasn't been written yet.
+  pub fn flush(&mut self) {
+    if self.unsaved == 0 {
+      return;
+    }
+
+    match self.save() {
+      Ok(()) => self.unsaved = 0,
+      Err(err) => warn!("{}", err),
+    }
+    self.last_save = Instant::now();
+  }
+
+  pub fn save(&self) -> Result<(), String> {
+    let error = |e: std::io::Error| format!("failed to save {}: {}", self.filepath.display(), e);
+
+    if let Some(dir) = self.filepath.parent() {
+      fs::create_dir_all(dir).map_err(error)?;
+    }
+
+    // written aside first, so a crash never leaves a truncated file
+    let tmp = self.filepath.with_extension("tmp");
+    {
+      let mut writer = BufWriter::new(File::create(&tmp).map_err(error)?);
+      for (text, count) in &self.counts {
+        writeln!(writer, "{}\t{}", text, count).map_err(error)?;
+      }
+      writer.flush().map_err(error)?;
+    }
+
+    fs::rename(&tmp, &self.filepath).map_err(error)
+  }
+}
+
+impl Drop for WordFrequency {
+  fn drop(&mut self) {
+    self.flush();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn filepath(name: &str) -> PathBuf {
+    let res = std::env::temp_dir().join(format!("ime-neovim-{}-{}.freq", name, std::process::id()));
+    let _ = fs::remove_file(&res);
+    res
+  }
+
+  #[test]
+  fn saves_after_enough_commits() {
+    let path = filepath("commits");
+    let mut frequency = WordFrequency::load_path(path.clone());
+
+    for _ in 1..SAVE_COMMITS {
+      frequency.learn("中国");
+    }
+    assert!(!path.exists());
+
+    frequency.learn("中国");
+    assert_eq!(
+      WordFrequency::load_path(path.clone()).get("中国"),
+      SAVE_COMMITS
+    );
+    let _ = fs::remove_file(&path);
+  }
+
+  #[test]
+  fn saves_when_dropped() {
+    let path = filepath("drop");
+    {
+      let mut frequency = WordFrequency::load_path(path.clone());
+      frequency.learn("中国");
+      frequency.learn("中国");
+    }
+
+    assert_eq!(WordFrequency::load_path(path.clone()).get("中国"), 2);
+    let _ = fs::remove_file(&path);
+  }
+
+  #[test]
+  fn shared_by_path() {
+    let path = filepath("shared");
+    let first = WordFrequency::shared_path(path.clone());
+    let second = WordFrequency::shared_path(path.clone());
+    assert!(Arc::ptr_eq(&first, &second));
+
+    first.write().unwrap().learn("中国");
+    assert_eq!(second.read().unwrap().get("中国"), 1);
+    drop((first, second));
+
+    // loaded again once nobody holds it
+    assert_eq!(
+      WordFrequency::shared_path(path.clone())
+        .read()
+        .unwrap()
+        .get("中国"),
+      1
+    );
+    let _ = fs::remove_file(&path);
+  }
+}
@@ -0,0 +1,456 @@
+use crate::engine::candidate::Candidate;
+use crate::engine::engine::{
+  BackspaceResult, ConfirmResult, ContextId, CursorMotion, InputContext, CANDIDATE_BATCH,
+};
+use log::info;
+use std::cmp;
+use std::collections::HashMap;
+
+struct SubContext {
+  context: Box<dyn InputContext>,
+  weight: u32,
+}
+
+// a candidate of a sub context, `index`th of its engine
+struct MergedItem {
+  candidate: Candidate,
+  index: usize,
+  weight: u32,
+  engine: usize,
+}
+
+pub struct MixedContext {
+  id: ContextId,
+  contexts: Vec<SubContext>,
+  keep_duplicates: bool,
+  // codes of the first context with candidates
+  codes: Vec<String>,
+  // index of the context `codes` come from
+  shown: usize,
+  // text committed by a sub context, not taken yet
+  committed: Option<String>,
+  // candidates merged so far, from `fetch` candidates of each context, and
+  // if they are all the candidates
+  merged: Vec<MergedItem>,
+  fetch: usize,
+  exhausted: bool,
+}
+
+impl MixedContext {
+  pub fn new(contexts: Vec<(Box<dyn InputContext>, u32)>, keep_duplicates: bool) -> MixedContext {
+    MixedContext {
+      id: ContextId::new(),
+      contexts: contexts
+        .into_iter()
+        .map(|(context, weight)| SubContext { context, weight })
+        .collect(),
+      keep_duplicates,
+      codes: Vec::new(),
+      shown: 0,
+      committed: None,
+      merged: Vec::new(),
+      fetch: 0,
+      exhausted: false,
+    }
+  }
+
+  // candidates of every context interleaved by weight, the `i`th candidate of
+  // an engine of weight `w` is placed by `i / w`, at least `count` of them
+  // unless there are fewer
+  fn merge(&mut self, count: usize) -> &[MergedItem] {
+    if self.merged.len() >= count || self.exhausted {
+      return &self.merged;
+    }
+    let mut fetch = cmp::max(count, self.fetch * 2);
+
+    loop {
+      let mut items: Vec<MergedItem> = Vec::new();
+      let mut exhausted = true;
+
+      for (engine, sub) in self.contexts.iter_mut().enumerate() {
+        let candidates = sub.context.candidates(0, fetch);
+        if candidates.len() >= fetch {
+          exhausted = false;
+        }
+
+        items.extend(
+          candidates
+            .into_iter()
+            .enumerate()
+            .map(|(index, candidate)| MergedItem {
+              candidate,
+              index,
+              weight: sub.weight,
+              engine,
+            }),
+        );
+      }
+
+      items.sort_by(|a, b| {
+        (a.index * b.weight as usize)
+          .cmp(&(b.index * a.weight as usize))
+          .then(a.engine.cmp(&b.engine))
+      });
+
+      let mut res: Vec<MergedItem> = Vec::new();
+      let mut seen: HashMap<String, usize> = HashMap::new();
+      for item in items {
+        if self.keep_duplicates {
+          res.push(item);
+          continue;
+        }
+
+        match seen.get(&item.candidate.text) {
+          // the first one wins, but keeps the codes of the others
+          Some(&i) => {
+            if res[i].candidate.codes.is_empty() {
+              res[i].candidate.codes = item.candidate.codes;
+            }
+          }
+          None => {
+            seen.insert(item.candidate.text.clone(), res.len());
+            res.push(item);
+          }
+        }
+      }
+
+      // duplicates may leave fewer candidates than asked
+      if res.len() >= count || exhausted {
+        self.merged = res;
+        self.fetch = fetch;
+        self.exhausted = exhausted;
+        return &self.merged;
+      }
+      fetch *= 2;
+    }
+  }
+
+  // the input changed, candidates are merged again
+  fn reset_merged(&mut self) {
+    self.merged.clear();
+    self.fetch = 0;
+    self.exhausted = false;
+  }
+
+  // erases a key of every context, a context left without input can't
+  // follow the others any more and is dropped
+  fn erase(&mut self, erase: fn(&mut dyn InputContext) -> BackspaceResult) -> BackspaceResult {
+    let mut cancelled = Vec::new();
+    for (i, sub) in self.contexts.iter_mut().enumerate() {
+      if let BackspaceResult::Cancel = erase(sub.context.as_mut()) {
+        cancelled.push(i);
+      }
+    }
+    if cancelled.len() == self.contexts.len() {
+      return BackspaceResult::Cancel;
+    }
+    for i in cancelled.into_iter().rev() {
+      info!("context {} cancelled, dropped", i);
+      self.contexts.remove(i);
+    }
+    self.reset_merged();
+    self.refresh_codes();
+
+    BackspaceResult::Candidates(self.candidates(0, CANDIDATE_BATCH), self.codes())
+  }
+
+  fn refresh_codes(&mut self) {
+    self.shown = self
+      .contexts
+      .iter_mut()
+      .position(|sub| !sub.context.candidates(0, 1).is_empty())
+      .unwrap_or(0);
+    self.codes = self.contexts[self.shown].context.codes();
+  }
+}
+
+impl InputContext for MixedContext {
+  fn feed(&mut self, ch: char) -> (Vec<Candidate>, Vec<String>) {
+    for sub in self.contexts.iter_mut() {
+      sub.context.feed(ch);
+    }
+
+    // the rest of the input belongs to the context committing on its own
+    let mut committed = None;
+    for (i, sub) in self.contexts.iter_mut().enumerate() {
+      if let Some(text) = sub.context.take_commit() {
+        committed = Some((i, text));
+        break;
+      }
+    }
+    if let Some((i, text)) = committed {
+      let sub = self.contexts.swap_remove(i);
+      self.contexts = vec![sub];
+      self.committed = Some(text);
+    }
+    self.reset_merged();
+    self.refresh_codes();
+
+    info!("feed {}, codes: {:?}", ch, self.codes);
+
+    (self.candidates(0, CANDIDATE_BATCH), self.codes())
+  }
+
+  fn backspace(&mut self) -> BackspaceResult {
+    self.erase(|context| context.backspace())
+  }
+
+  fn delete(&mut self) -> BackspaceResult {
+    self.erase(|context| context.delete())
+  }
+
+  fn move_cursor(&mut self, motion: CursorMotion) {
+    for sub in self.contexts.iter_mut() {
+      sub.context.move_cursor(motion);
+    }
+    self.reset_merged();
+    self.refresh_codes();
+  }
+
+  fn cursor(&self) -> usize {
+    self.contexts[self.shown].context.cursor()
+  }
+
+  fn candidates(&mut self, page: usize, per_page: usize) -> Vec<Candidate> {
+    let merged = self.merge((page + 1) * per_page);
+    let end = cmp::min((page + 1) * per_page, merged.len());
+    let start = cmp::min(page * per_page, end);
+
+    merged[start..end]
+      .iter()
+      .map(|item| item.candidate.clone())
+      .collect()
+  }
+
+  // the context of the candidate confirms it, the rest of the input belongs
+  // to that context only
+  fn confirm(&mut self, index: usize) -> Option<ConfirmResult> {
+    let (engine, sub_index) = self
+      .merge(index + 1)
+      .get(index)
+      .map(|item| (item.engine, item.index))?;
+
+    match self.contexts[engine].context.confirm(sub_index)? {
+      ConfirmResult::Done(text) => Some(ConfirmResult::Done(text)),
+      ConfirmResult::Partial(text, ..) => {
+        let sub = self.contexts.swap_remove(engine);
+        self.contexts = vec![sub];
+        self.reset_merged();
+        self.refresh_codes();
+
+        Some(ConfirmResult::Partial(
+          text,
+          self.candidates(0, CANDIDATE_BATCH),
+          self.codes(),
+        ))
+      }
+    }
+  }
+
+  fn has_more(&mut self, page: usize, per_page: usize) -> bool {
+    self.merge((page + 1) * per_page + 1).len() > (page + 1) * per_page
+  }
+
+  fn id(&self) -> ContextId {
+    self.id.clone()
+  }
+
+  fn codes(&self) -> Vec<String> {
+    self.codes.clone()
+  }
+
+  fn take_commit(&mut self) -> Option<String> {
+    self.committed.take()
+  }
+
+  fn accepts_tone(&self, key: char) -> bool {
+    self
+      .contexts
+      .iter()
+      .any(|sub| sub.context.accepts_tone(key))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::engine::pinyin::{
+    FuzzySyllables, PinyinContext, PinyinDictionary, Segmenter, Syllable, WordEntry,
+  };
+  use std::sync::Arc;
+
+  // a context with the same candidates for any input
+  struct Fixed {
+    candidates: Vec<Candidate>,
+    keys: usize,
+  }
+
+  impl InputContext for Fixed {
+    fn feed(&mut self, _ch: char) -> (Vec<Candidate>, Vec<String>) {
+      self.keys += 1;
+      (self.candidates.clone(), self.codes())
+    }
+
+    fn candidates(&mut self, page: usize, per_page: usize) -> Vec<Candidate> {
+      self
+        .candidates
+        .iter()
+        .skip(page * per_page)
+        .take(per_page)
+        .cloned()
+        .collect()
+    }
+
+    fn has_more(&mut self, page: usize, per_page: usize) -> bool {
+      self.candidates.len() > (page + 1) * per_page
+    }
+
+    fn backspace(&mut self) -> BackspaceResult {
+      self.keys -= 1;
+      if self.keys == 0 {
+        return BackspaceResult::Cancel;
+      }
+      BackspaceResult::Candidates(self.candidates.clone(), self.codes())
+    }
+
+    fn delete(&mut self) -> BackspaceResult {
+      BackspaceResult::Candidates(self.candidates.clone(), self.codes())
+    }
+
+    fn move_cursor(&mut self, _motion: CursorMotion) {}
+
+    fn cursor(&self) -> usize {
+      self.keys
+    }
+
+    fn id(&self) -> ContextId {
+      ContextId::new()
+    }
+
+    fn codes(&self) -> Vec<String> {
+      vec![]
+    }
+  }
+
+  fn fixed(texts: &[&str]) -> Box<dyn InputContext> {
+    Box::new(Fixed {
+      candidates: texts
+        .iter()
+        .map(|x| Candidate::prefect(x.to_string()))
+        .collect(),
+      keys: 0,
+    })
+  }
+
+  fn texts(ctx: &mut MixedContext) -> Vec<String> {
+    ctx.candidates(0, 10).into_iter().map(|x| x.text).collect()
+  }
+
+  #[test]
+  fn merged_by_weight() {
+    let mut ctx = MixedContext::new(
+      vec![
+        (fixed(&["a1", "a2", "a3", "a4"]), 2),
+        (fixed(&["b1", "b2"]), 1),
+      ],
+      false,
+    );
+    ctx.feed('a');
+
+    assert_eq!(texts(&mut ctx), ["a1", "b1", "a2", "a3", "b2", "a4"]);
+    assert!(!ctx.has_more(0, 6));
+  }
+
+  #[test]
+  fn duplicates() {
+    let mut with_codes = Candidate::prefect("x".to_string());
+    with_codes.codes = vec!['x', 'c'];
+    let other = || -> Box<dyn InputContext> {
+      Box::new(Fixed {
+        candidates: vec![with_codes.clone(), Candidate::prefect("z".to_string())],
+        keys: 0,
+      })
+    };
+
+    let mut ctx = MixedContext::new(vec![(fixed(&["x", "y"]), 1), (other(), 1)], false);
+    ctx.feed('a');
+    assert_eq!(texts(&mut ctx), ["x", "y", "z"]);
+    // the first one keeps the codes of the dropped one
+    assert_eq!(ctx.candidates(0, 1)[0].codes, vec!['x', 'c']);
+
+    let mut ctx = MixedContext::new(vec![(fixed(&["x", "y"]), 1), (other(), 1)], true);
+    ctx.feed('a');
+    assert_eq!(texts(&mut ctx), ["x", "x", "y", "z"]);
+  }
+
+  #[test]
+  fn cancelled_contexts_are_dropped() {
+    let mut ctx = MixedContext::new(vec![(fixed(&["a"]), 1), (fixed(&["b"]), 1)], false);
+    ctx.feed('a');
+    ctx.feed('b');
+    // the second context lost a key on its own
+    ctx.contexts[1].context.backspace();
+
+    match ctx.backspace() {
+      BackspaceResult::Candidates(candidates, _) => assert_eq!(candidates[0].text, "a"),
+      BackspaceResult::Cancel => panic!("unexpected cancel"),
+    }
+    assert_eq!(ctx.contexts.len(), 1);
+    assert!(matches!(ctx.backspace(), BackspaceResult::Cancel));
+  }
+
+  fn pinyin_context() -> Box<dyn InputContext> {
+    let segmenter = Segmenter::new(None, FuzzySyllables::new(vec![]).unwrap(), vec![]);
+    let mut dictionary = PinyinDictionary::new();
+    for (text, pinyin) in &[("中国", "zhong guo"), ("中", "zhong"), ("人", "ren")] {
+      let syllables: Vec<Syllable> = pinyin
+        .split(' ')
+        .map(|x| segmenter.table().parse(x).unwrap())
+        .collect();
+      dictionary.insert(
+        &syllables,
+        WordEntry {
+          text: text.to_string(),
+          priority: 100,
+          tones: vec![None; syllables.len()],
+        },
+      );
+    }
+
+    Box::new(PinyinContext::new(
+      Arc::new(dictionary),
+      Arc::new(segmenter),
+      None,
+      None,
+      None,
+    ))
+  }
+
+  #[test]
+  fn confirm_keeps_the_rest_of_the_input() {
+    let mut ctx = MixedContext::new(vec![(pinyin_context(), 1), (pinyin_context(), 1)], false);
+    for ch in "zhongguoren".chars() {
+      ctx.feed(ch);
+    }
+
+    let index = ctx
+      .candidates(0, 10)
+      .iter()
+      .position(|x| x.text == "中国")
+      .unwrap();
+    match ctx.confirm(index) {
+      Some(ConfirmResult::Partial(text, candidates, codes)) => {
+        assert_eq!(text, "中国");
+        assert_eq!(candidates[0].text, "人");
+        assert_eq!(codes, vec!["ren"]);
+      }
+      res => panic!("unexpected {:?}", res),
+    }
+    assert_eq!(ctx.contexts.len(), 1);
+
+    match ctx.confirm(0) {
+      Some(ConfirmResult::Done(text)) => assert_eq!(text, "人"),
+      res => panic!("unexpected {:?}", res),
+    }
+  }
+}
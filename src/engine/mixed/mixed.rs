@@ -0,0 +1,159 @@
+use super::input_context::MixedContext;
+use crate::engine::engine::{IMEngine, InputContext};
+use crate::engine::Configuration;
+use async_std::sync::Mutex;
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::rc::Rc;
+use std::sync::Arc;
+
+/// Runs several engines against the same input, e.g. pinyin and a code
+/// table, merging their candidates.
+pub struct MixedEngine {
+  engines: Vec<(Box<dyn IMEngine>, u32)>,
+  keep_duplicates: bool,
+}
+
+impl IMEngine for MixedEngine {
+  fn start_context(&self) -> Rc<RefCell<dyn InputContext>> {
+    Rc::new(RefCell::new(self.context()))
+  }
+
+  fn start_context_async(&self) -> Arc<Mutex<dyn InputContext>> {
+    Arc::new(Mutex::new(self.context()))
+  }
+
+  fn new_context(&self) -> Box<dyn InputContext> {
+    Box::new(self.context())
+  }
+
+  fn keycodes(&self) -> HashSet<char> {
+    self
+      .engines
+      .iter()
+      .flat_map(|(engine, _)| engine.keycodes())
+      .collect()
+  }
+
+  fn tone_keys(&self) -> HashSet<char> {
+    self
+      .engines
+      .iter()
+      .flat_map(|(engine, _)| engine.tone_keys())
+      .collect()
+  }
+
+  // keys starting an input of any engine aren't separators
+  fn separators(&self) -> HashSet<char> {
+    let mut starts: HashSet<char> = HashSet::new();
+    let mut res: HashSet<char> = HashSet::new();
+    for (engine, _) in &self.engines {
+      let separators = engine.separators();
+      starts.extend(
+        engine
+          .keycodes()
+          .into_iter()
+          .filter(|x| !separators.contains(x)),
+      );
+      res.extend(separators);
+    }
+
+    res.retain(|x| !starts.contains(x));
+    res
+  }
+
+  fn commit(&mut self, text: &str) {
+    for (engine, _) in self.engines.iter_mut() {
+      engine.commit(text);
+    }
+  }
+
+  fn flush(&mut self) {
+    for (engine, _) in self.engines.iter_mut() {
+      engine.flush();
+    }
+  }
+
+  // phrases go to the first engine supporting them
+  fn add_phrase(&mut self, text: &str, code: Option<&str>) -> Result<String, String> {
+    let mut errors = Vec::new();
+
+    for (engine, _) in self.engines.iter_mut() {
+      match engine.add_phrase(text, code) {
+        Ok(code) => return Ok(code),
+        Err(err) => errors.push(err),
+      }
+    }
+
+    Err(errors.join("; "))
+  }
+
+  fn reverse_lookup(&self, text: &str) -> Vec<String> {
+    self
+      .engines
+      .iter()
+      .map(|(engine, _)| engine.reverse_lookup(text))
+      .find(|codes| !codes.is_empty())
+      .unwrap_or_default()
+  }
+
+  fn delete_phrase(&mut self, text: &str, code: Option<&str>) -> Result<(), String> {
+    let mut errors = Vec::new();
+
+    for (engine, _) in self.engines.iter_mut() {
+      if let Err(err) = engine.delete_phrase(text, code) {
+        errors.push(err);
+      }
+    }
+
+    if errors.len() == self.engines.len() {
+      Err(errors.join("; "))
+    } else {
+      Ok(())
+    }
+  }
+}
+
+impl MixedEngine {
+  pub fn new(config: Configuration) -> Result<MixedEngine, String> {
+    if let Configuration::Mixed {
+      engines,
+      keep_duplicates,
+    } = config
+    {
+      if engines.is_empty() {
+        return Err("expect at least 1 engine".to_string());
+      }
+
+      let mut res = MixedEngine {
+        engines: Vec::new(),
+        keep_duplicates,
+      };
+      for (i, sub) in engines.into_iter().enumerate() {
+        if sub.weight == 0 {
+          return Err(format!("weight of engine {} should be positive", i));
+        }
+
+        let engine = sub
+          .engine
+          .boxed_engine()
+          .map_err(|e| format!("engine {}: {}", i, e))?;
+        res.engines.push((engine, sub.weight));
+      }
+      Ok(res)
+    } else {
+      Err("expect mixed configuration".to_string())
+    }
+  }
+
+  fn context(&self) -> MixedContext {
+    MixedContext::new(
+      self
+        .engines
+        .iter()
+        .map(|(engine, weight)| (engine.shared_context(), *weight))
+        .collect(),
+      self.keep_duplicates,
+    )
+  }
+}
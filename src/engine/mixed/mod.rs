@@ -0,0 +1,5 @@
+mod input_context;
+mod mixed;
+
+pub use input_context::MixedContext;
+pub use mixed::MixedEngine;
@@ -2,9 +2,11 @@ pub mod base;
 pub mod candidate;
 pub mod codetable;
 pub mod engine;
+pub mod frequency;
 pub mod keymap;
+pub mod mixed;
 pub mod pinyin;
 
-pub use base::Configuration;
+pub use base::{Configuration, Engines, Profiles, SubEngine};
 pub use candidate::{Candidate, MatchType};
-pub use engine::{BackspaceResult, IMEngine, InputContext};
+pub use engine::{BackspaceResult, ConfirmResult, CursorMotion, IMEngine, InputContext};
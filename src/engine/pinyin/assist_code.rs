@@ -1,8 +1,8 @@
 use crate::data::PersistentTrie;
 use crate::path::LocalDataPath;
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
 use std::io::{BufRead, BufReader};
-use std::collections::HashSet;
 
 #[derive(Clone, Debug)]
 struct AssistResult(String);
@@ -10,17 +10,29 @@ struct AssistResult(String);
 pub struct AssistCode {
   trie: PersistentTrie<char, AssistResult>,
   keycodes: HashSet<char>,
+  // character to its assist codes
+  codes: HashMap<String, Vec<String>>,
+  max_length: usize,
 }
 
 impl AssistCode {
-  pub fn new(filename: &str) -> AssistCode {
+  pub fn new(filename: &str) -> Result<AssistCode, String> {
+    let filepath = LocalDataPath::new().sub("assist_code").file(filename);
+
+    let file = File::open(&filepath)
+      .map_err(|e| format!("failed to open assist code {}: {}", filepath.display(), e))?;
+
+    Ok(AssistCode::parse(BufReader::new(file)))
+  }
+
+  /// Parses lines of a character and its assist codes, separated by tab.
+  pub fn parse<R: BufRead>(reader: R) -> AssistCode {
     let mut assist_code = AssistCode {
       trie: PersistentTrie::new(),
       keycodes: HashSet::new(),
+      codes: HashMap::new(),
+      max_length: 0,
     };
-    let filepath = LocalDataPath::new().sub("assist_code").file(filename);
-
-    let reader = BufReader::new(File::open(filepath).unwrap());
 
     for l in reader.lines() {
       if let Ok(line) = l {
@@ -35,17 +47,37 @@ impl AssistCode {
           for ch in v[i].chars().collect::<Vec<char>>() {
             assist_code.keycodes.insert(ch);
           }
+          assist_code
+            .codes
+            .entry(v[0].to_string())
+            .or_default()
+            .push(v[i].to_string());
+          assist_code.max_length = assist_code.max_length.max(v[i].chars().count());
         }
       }
     }
 
-    assist_code.trie.maintain();
-
     assist_code
   }
 
   pub fn keycodes(&self) -> HashSet<char> {
     self.keycodes.clone()
   }
-}
 
+  pub fn max_length(&self) -> usize {
+    self.max_length
+  }
+
+  /// If one of the assist codes of `text` starts with `keys`, returns the
+  /// shortest rest of such codes.
+  pub fn remain(&self, text: &str, keys: &[char]) -> Option<Vec<char>> {
+    self
+      .codes
+      .get(text)?
+      .iter()
+      .map(|code| code.chars().collect::<Vec<char>>())
+      .filter(|code| code.starts_with(keys))
+      .map(|code| code[keys.len()..].to_vec())
+      .min_by_key(|remain| remain.len())
+  }
+}
@@ -1,6 +1,6 @@
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum PinyinInitials {
   B,
   P,
@@ -20,13 +20,14 @@ pub enum PinyinInitials {
   CH,
   SH,
   R,
+  Z,
   C,
   S,
   Y,
   W,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum PinyinFinals {
   A,
   O,
@@ -43,6 +44,12 @@ pub enum PinyinFinals {
   IE,
   VE,
   ER,
+  IA,
+  UA,
+  UO,
+  UE,
+  IAO,
+  UAI,
   AN,
   EN,
   IN,
@@ -52,64 +59,142 @@ pub enum PinyinFinals {
   ENG,
   ING,
   ONG,
+  IAN,
+  UAN,
+  IANG,
+  UANG,
+  IONG,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum PinyinCode {
   Initials(PinyinInitials),
   Finals(PinyinFinals),
 }
 
+static INITIALS: [(&str, PinyinInitials); 23] = [
+  ("b", PinyinInitials::B),
+  ("p", PinyinInitials::P),
+  ("m", PinyinInitials::M),
+  ("f", PinyinInitials::F),
+  ("d", PinyinInitials::D),
+  ("t", PinyinInitials::T),
+  ("n", PinyinInitials::N),
+  ("l", PinyinInitials::L),
+  ("g", PinyinInitials::G),
+  ("k", PinyinInitials::K),
+  ("h", PinyinInitials::H),
+  ("j", PinyinInitials::J),
+  ("q", PinyinInitials::Q),
+  ("x", PinyinInitials::X),
+  ("zh", PinyinInitials::ZH),
+  ("ch", PinyinInitials::CH),
+  ("sh", PinyinInitials::SH),
+  ("r", PinyinInitials::R),
+  ("z", PinyinInitials::Z),
+  ("c", PinyinInitials::C),
+  ("s", PinyinInitials::S),
+  ("y", PinyinInitials::Y),
+  ("w", PinyinInitials::W),
+];
+
+static FINALS: [(&str, PinyinFinals); 35] = [
+  ("a", PinyinFinals::A),
+  ("o", PinyinFinals::O),
+  ("e", PinyinFinals::E),
+  ("i", PinyinFinals::I),
+  ("u", PinyinFinals::U),
+  ("v", PinyinFinals::V),
+  ("ai", PinyinFinals::AI),
+  ("ei", PinyinFinals::EI),
+  ("ui", PinyinFinals::UI),
+  ("ao", PinyinFinals::AO),
+  ("ou", PinyinFinals::OU),
+  ("iu", PinyinFinals::IU),
+  ("ie", PinyinFinals::IE),
+  ("ve", PinyinFinals::VE),
+  ("er", PinyinFinals::ER),
+  ("ia", PinyinFinals::IA),
+  ("ua", PinyinFinals::UA),
+  ("uo", PinyinFinals::UO),
+  ("ue", PinyinFinals::UE),
+  ("iao", PinyinFinals::IAO),
+  ("uai", PinyinFinals::UAI),
+  ("an", PinyinFinals::AN),
+  ("en", PinyinFinals::EN),
+  ("in", PinyinFinals::IN),
+  ("un", PinyinFinals::UN),
+  ("vn", PinyinFinals::VN),
+  ("ang", PinyinFinals::ANG),
+  ("eng", PinyinFinals::ENG),
+  ("ing", PinyinFinals::ING),
+  ("ong", PinyinFinals::ONG),
+  ("ian", PinyinFinals::IAN),
+  ("uan", PinyinFinals::UAN),
+  ("iang", PinyinFinals::IANG),
+  ("uang", PinyinFinals::UANG),
+  ("iong", PinyinFinals::IONG),
+];
+
+impl PinyinInitials {
+  pub fn parse(s: &str) -> Option<PinyinInitials> {
+    INITIALS
+      .iter()
+      .find(|(spelling, _)| *spelling == s)
+      .map(|(_, code)| *code)
+  }
+
+  pub fn as_str(&self) -> &'static str {
+    INITIALS
+      .iter()
+      .find(|(_, code)| code == self)
+      .map(|(spelling, _)| *spelling)
+      .unwrap()
+  }
+
+  pub fn all() -> impl Iterator<Item = PinyinInitials> {
+    INITIALS.iter().map(|(_, code)| *code)
+  }
+}
+
+impl PinyinFinals {
+  pub fn parse(s: &str) -> Option<PinyinFinals> {
+    FINALS
+      .iter()
+      .find(|(spelling, _)| *spelling == s)
+      .map(|(_, code)| *code)
+  }
+
+  pub fn as_str(&self) -> &'static str {
+    FINALS
+      .iter()
+      .find(|(_, code)| code == self)
+      .map(|(spelling, _)| *spelling)
+      .unwrap()
+  }
+
+  pub fn all() -> impl Iterator<Item = PinyinFinals> {
+    FINALS.iter().map(|(_, code)| *code)
+  }
+}
+
+impl PinyinCode {
+  pub fn parse(s: &str) -> Option<PinyinCode> {
+    let s = s.to_lowercase();
+
+    if let Some(initial) = PinyinInitials::parse(&s) {
+      Some(PinyinCode::Initials(initial))
+    } else {
+      Some(PinyinCode::Finals(PinyinFinals::parse(&s)?))
+    }
+  }
+}
+
 impl From<String> for PinyinCode {
   fn from(s: String) -> PinyinCode {
-    match s.to_lowercase().as_str() {
-      "b" => (PinyinCode::Initials(PinyinInitials::B)),
-      "p" => (PinyinCode::Initials(PinyinInitials::P)),
-      "m" => (PinyinCode::Initials(PinyinInitials::M)),
-      "f" => (PinyinCode::Initials(PinyinInitials::F)),
-      "d" => (PinyinCode::Initials(PinyinInitials::D)),
-      "t" => (PinyinCode::Initials(PinyinInitials::T)),
-      "n" => (PinyinCode::Initials(PinyinInitials::N)),
-      "l" => (PinyinCode::Initials(PinyinInitials::L)),
-      "g" => (PinyinCode::Initials(PinyinInitials::G)),
-      "k" => (PinyinCode::Initials(PinyinInitials::K)),
-      "h" => (PinyinCode::Initials(PinyinInitials::H)),
-      "j" => (PinyinCode::Initials(PinyinInitials::J)),
-      "q" => (PinyinCode::Initials(PinyinInitials::Q)),
-      "x" => (PinyinCode::Initials(PinyinInitials::X)),
-      "zh" => (PinyinCode::Initials(PinyinInitials::ZH)),
-      "ch" => (PinyinCode::Initials(PinyinInitials::CH)),
-      "sh" => (PinyinCode::Initials(PinyinInitials::SH)),
-      "r" => (PinyinCode::Initials(PinyinInitials::R)),
-      "c" => (PinyinCode::Initials(PinyinInitials::C)),
-      "s" => (PinyinCode::Initials(PinyinInitials::S)),
-      "y" => (PinyinCode::Initials(PinyinInitials::Y)),
-      "w" => (PinyinCode::Initials(PinyinInitials::W)),
-      "a" => (PinyinCode::Finals(PinyinFinals::A)),
-      "o" => (PinyinCode::Finals(PinyinFinals::O)),
-      "e" => (PinyinCode::Finals(PinyinFinals::E)),
-      "i" => (PinyinCode::Finals(PinyinFinals::I)),
-      "u" => (PinyinCode::Finals(PinyinFinals::U)),
-      "v" => (PinyinCode::Finals(PinyinFinals::V)),
-      "ai" => (PinyinCode::Finals(PinyinFinals::AI)),
-      "ei" => (PinyinCode::Finals(PinyinFinals::EI)),
-      "ui" => (PinyinCode::Finals(PinyinFinals::UI)),
-      "ao" => (PinyinCode::Finals(PinyinFinals::AO)),
-      "ou" => (PinyinCode::Finals(PinyinFinals::OU)),
-      "iu" => (PinyinCode::Finals(PinyinFinals::IU)),
-      "ie" => (PinyinCode::Finals(PinyinFinals::IE)),
-      "ve" => (PinyinCode::Finals(PinyinFinals::VE)),
-      "er" => (PinyinCode::Finals(PinyinFinals::ER)),
-      "an" => (PinyinCode::Finals(PinyinFinals::AN)),
-      "en" => (PinyinCode::Finals(PinyinFinals::EN)),
-      "in" => (PinyinCode::Finals(PinyinFinals::IN)),
-      "un" => (PinyinCode::Finals(PinyinFinals::UN)),
-      "vn" => (PinyinCode::Finals(PinyinFinals::VN)),
-      "ang" => (PinyinCode::Finals(PinyinFinals::ANG)),
-      "eng" => (PinyinCode::Finals(PinyinFinals::ENG)),
-      "ing" => (PinyinCode::Finals(PinyinFinals::ING)),
-      "ong" => (PinyinCode::Finals(PinyinFinals::ONG)),
-      _ => panic!("load pinyin code failed, unknown {}", s.to_lowercase()),
+    match PinyinCode::parse(&s) {
+      Some(code) => code,
+      None => panic!("load pinyin code failed, unknown {}", s.to_lowercase()),
     }
   }
 }
@@ -0,0 +1,313 @@
+use super::codes::PinyinCode;
+use super::segment::Segment;
+use super::syllable::{Syllable, SyllableTable};
+use crate::data::{PersistentNode, PersistentTrie, TrieBuilder};
+use crate::engine::candidate::MatchType;
+use crate::path::LocalDataPath;
+use std::cmp::max;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::sync::Arc;
+
+#[derive(Debug)]
+pub struct WordEntry {
+  pub text: String,
+  pub priority: u32,
+  // tone of each syllable, if written in the database
+  pub tones: Vec<Option<u8>>,
+}
+
+type NodeType = Arc<PersistentNode<PinyinCode, WordEntry>>;
+
+#[derive(Debug)]
+pub struct LookupItem {
+  pub text: String,
+  pub priority: u32,
+  // number of segments the word covers
+  pub consumed: usize,
+  pub match_type: MatchType,
+}
+
+/// Words keyed by the initials and finals of their syllables.
+pub struct PinyinDictionary {
+  trie: PersistentTrie<PinyinCode, WordEntry>,
+}
+
+impl PinyinDictionary {
+  pub fn new() -> PinyinDictionary {
+    PinyinDictionary {
+      trie: PersistentTrie::new(),
+    }
+  }
+
+  /// Builds the dictionary from database files in one pass.
+  pub fn files(filenames: &[String], table: &SyllableTable) -> Result<PinyinDictionary, String> {
+    let mut builder = TrieBuilder::new();
+
+    for filename in filenames {
+      PinyinDictionary::load(&mut builder, filename, table)?;
+    }
+
+    Ok(PinyinDictionary {
+      trie: builder.build(),
+    })
+  }
+
+  /// Loads `filename` from the pinyin data directory, each line formatted as
+  /// `text<TAB>pinyin[<TAB>frequency]`:
+  ///
+  /// - syllables of `pinyin` are separated by spaces or `'`, each written
+  ///   without tone (`zhong`), with a tone number (`zhong1`) or with a tone
+  ///   mark (`zhōng`), tones written filter the word by tones typed,
+  /// - `text` has one character for each syllable,
+  /// - `frequency` is a non-negative integer, 100 if omitted,
+  /// - empty lines and lines starting with `#` are skipped.
+  fn load(
+    builder: &mut TrieBuilder<PinyinCode, WordEntry>,
+    filename: &str,
+    table: &SyllableTable,
+  ) -> Result<(), String> {
+    let filepath = LocalDataPath::new().sub("pinyin").file(filename);
+
+    let file = File::open(&filepath).map_err(|e| {
+      format!(
+        "failed to open pinyin database {}: {}",
+        filepath.display(),
+        e
+      )
+    })?;
+
+    PinyinDictionary::parse(builder, filename, BufReader::new(file), table)
+  }
+
+  fn parse<R: BufRead>(
+    builder: &mut TrieBuilder<PinyinCode, WordEntry>,
+    filename: &str,
+    reader: R,
+    table: &SyllableTable,
+  ) -> Result<(), String> {
+    for (lineno, l) in reader.lines().enumerate() {
+      let error = |msg: String| format!("{}:{}: {}", filename, lineno + 1, msg);
+
+      let line = l.map_err(|e| error(e.to_string()))?;
+      let line = line.trim();
+      if line.is_empty() || line.starts_with('#') {
+        continue;
+      }
+
+      let v: Vec<&str> = line.split('\t').collect();
+      if v.len() < 2 || v.len() > 3 {
+        return Err(error(
+          "expect text, pinyin and frequency separated by tab".to_string(),
+        ));
+      }
+      if v[0].is_empty() {
+        return Err(error("empty text".to_string()));
+      }
+
+      let (syllables, tones): (Vec<Syllable>, Vec<Option<u8>>) = v[1]
+        .split(|ch| ch == ' ' || ch == '\'')
+        .filter(|s| !s.is_empty())
+        .map(|s| {
+          table
+            .parse_toned(s)
+            .ok_or_else(|| error(format!("unknown syllable '{}'", s)))
+        })
+        .collect::<Result<Vec<(Syllable, Option<u8>)>, String>>()?
+        .into_iter()
+        .unzip();
+      if syllables.is_empty() {
+        return Err(error("empty pinyin".to_string()));
+      }
+      let len = v[0].chars().count();
+      if len != syllables.len() {
+        return Err(error(format!(
+          "{} characters but {} syllables",
+          len,
+          syllables.len()
+        )));
+      }
+
+      let priority = match v.get(2) {
+        Some(x) => x
+          .parse::<u32>()
+          .map_err(|_| error(format!("invalid frequency '{}'", x)))?,
+        None => 100,
+      };
+
+      builder.insert(
+        PinyinDictionary::codes(&syllables).iter(),
+        WordEntry {
+          text: v[0].to_string(),
+          priority,
+          tones,
+        },
+      );
+    }
+
+    Ok(())
+  }
+
+  pub fn insert(&mut self, syllables: &[Syllable], entry: WordEntry) {
+    self.trie = self
+      .trie
+      .insert(PinyinDictionary::codes(syllables).iter(), entry);
+  }
+
+  fn codes(syllables: &[Syllable]) -> Vec<PinyinCode> {
+    syllables.iter().flat_map(|s| s.codes()).collect()
+  }
+
+  fn walk(node: &NodeType, syllable: &Syllable) -> Option<NodeType> {
+    let mut cur = node.clone();
+
+    for code in syllable.codes() {
+      cur = cur.child(&code)?;
+    }
+
+    Some(cur)
+  }
+
+  // if tones typed for `segments` are those of `entry`, tones not written
+  // match any
+  fn tones_match(entry: &WordEntry, segments: &[Segment]) -> bool {
+    segments
+      .iter()
+      .zip(entry.tones.iter())
+      .all(|(segment, tone)| match (segment.tone, tone) {
+        (Some(x), Some(y)) => x == *y,
+        _ => true,
+      })
+  }
+
+  /// Words matching one or more leading segments of `segments`, and their
+  /// tones if typed.
+  pub fn lookup(&self, segments: &[Segment]) -> Vec<LookupItem> {
+    let mut res: Vec<LookupItem> = Vec::new();
+    let mut frontier: Vec<(NodeType, MatchType)> =
+      vec![(self.trie.root(), MatchType::PerfectMatch)];
+
+    for (i, segment) in segments.iter().enumerate() {
+      let mut next: Vec<(NodeType, MatchType)> = Vec::new();
+
+      for (node, match_type) in &frontier {
+        for (syllable, syllable_match) in &segment.syllables {
+          if let Some(child) = Self::walk(node, syllable) {
+            next.push((child, max(*match_type, *syllable_match)));
+          }
+        }
+      }
+
+      for (node, match_type) in &next {
+        for value in &node.values {
+          if !Self::tones_match(value, &segments[..=i]) {
+            continue;
+          }
+
+          res.push(LookupItem {
+            text: value.text.clone(),
+            priority: value.priority,
+            consumed: i + 1,
+            match_type: *match_type,
+          });
+        }
+      }
+
+      if next.is_empty() {
+        break;
+      }
+      frontier = next;
+    }
+
+    res
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::engine::pinyin::segment::Segmenter;
+  use crate::engine::pinyin::FuzzySyllables;
+
+  fn dictionary(
+    words: &[(&str, &str, Vec<Option<u8>>)],
+    table: &SyllableTable,
+  ) -> PinyinDictionary {
+    let mut res = PinyinDictionary::new();
+
+    for (text, pinyin, tones) in words {
+      let syllables: Vec<Syllable> = pinyin.split(' ').map(|x| table.parse(x).unwrap()).collect();
+      res.insert(
+        &syllables,
+        WordEntry {
+          text: text.to_string(),
+          priority: 100,
+          tones: tones.clone(),
+        },
+      );
+    }
+
+    res
+  }
+
+  fn lookup_all(dictionary: &PinyinDictionary, segmenter: &Segmenter, input: &str) -> Vec<String> {
+    let input: Vec<char> = input.chars().collect();
+    let mut res: Vec<String> = segmenter
+      .segment(&input)
+      .iter()
+      .flat_map(|segmentation| dictionary.lookup(segmentation))
+      .map(|item| item.text)
+      .collect();
+    res.sort();
+    res.dedup();
+
+    res
+  }
+
+  #[test]
+  fn filter_by_tone() {
+    let segmenter = Segmenter::new(
+      None,
+      FuzzySyllables::new(vec![]).unwrap(),
+      "12345".chars().collect(),
+    );
+    let dictionary = dictionary(
+      &[
+        ("妈", "ma", vec![Some(1)]),
+        ("麻", "ma", vec![Some(2)]),
+        ("马", "ma", vec![Some(3)]),
+        ("吗", "ma", vec![Some(5)]),
+        ("码", "ma", vec![None]),
+      ],
+      segmenter.table(),
+    );
+
+    assert_eq!(lookup_all(&dictionary, &segmenter, "ma3"), vec!["码", "马"]);
+    assert_eq!(lookup_all(&dictionary, &segmenter, "ma1"), vec!["妈", "码"]);
+    assert_eq!(lookup_all(&dictionary, &segmenter, "ma").len(), 5);
+  }
+
+  fn parse(content: &str, table: &SyllableTable) -> Result<(), String> {
+    let mut builder = TrieBuilder::new();
+    PinyinDictionary::parse(&mut builder, "test.txt", content.as_bytes(), table)
+  }
+
+  #[test]
+  fn parse_rows() {
+    let table = SyllableTable::new();
+
+    assert!(parse("# comment\n\n中国\tzhong guo\n西安\txi'an\t10\n", &table).is_ok());
+    assert_eq!(
+      parse("中\tzhong\n中国\tzhong\n", &table).unwrap_err(),
+      "test.txt:2: 2 characters but 1 syllables"
+    );
+    assert_eq!(
+      parse("中\tzhong guo\n", &table).unwrap_err(),
+      "test.txt:1: 1 characters but 2 syllables"
+    );
+    assert_eq!(
+      parse("中\tzhongx\n", &table).unwrap_err(),
+      "test.txt:1: unknown syllable 'zhongx'"
+    );
+  }
+}
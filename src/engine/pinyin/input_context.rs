@@ -0,0 +1,629 @@
+use super::assist_code::AssistCode;
+use super::dictionary::PinyinDictionary;
+use super::language_model::LanguageModel;
+use super::segment::{Segmentation, Segmenter, SEPARATOR};
+use crate::engine::candidate::{Candidate, MatchType};
+use crate::engine::engine::{
+  BackspaceResult, ConfirmResult, ContextId, CursorMotion, InputContext, CANDIDATE_BATCH,
+};
+use crate::engine::frequency::WordFrequency;
+use log::info;
+use std::cmp::{max, min, Ordering, Reverse};
+use std::collections::{BTreeMap, HashSet};
+use std::mem;
+use std::sync::{Arc, RwLock};
+
+// partial sentences kept at each syllable
+static SENTENCE_BEAM: usize = 16;
+
+pub struct PinyinContext {
+  id: ContextId,
+  dictionary: Arc<PinyinDictionary>,
+  segmenter: Arc<Segmenter>,
+  assist: Option<Arc<AssistCode>>,
+  frequency: Option<Arc<RwLock<WordFrequency>>>,
+  model: Option<Arc<LanguageModel>>,
+  input_sequence: Vec<char>,
+  // number of input keys before the cursor
+  cursor: usize,
+  segmentation: Segmentation,
+  // trailing keys which can't be segmented into syllables
+  unparsed: Vec<char>,
+  segmentations: Vec<Segmentation>,
+  // candidates of the current input generated so far
+  candidates: Vec<Candidate>,
+  // number of trailing input keys each candidate leaves
+  uncovered: Vec<usize>,
+  seen: HashSet<String>,
+  // words to look up by the number of input keys they leave, as the
+  // segmentation and the number of its segments they consume, generated from
+  // the fewest keys left
+  stages: BTreeMap<usize, Vec<(usize, usize)>>,
+}
+
+impl PinyinContext {
+  pub fn new(
+    dictionary: Arc<PinyinDictionary>,
+    segmenter: Arc<Segmenter>,
+    assist: Option<Arc<AssistCode>>,
+    frequency: Option<Arc<RwLock<WordFrequency>>>,
+    model: Option<Arc<LanguageModel>>,
+  ) -> PinyinContext {
+    PinyinContext {
+      id: ContextId::new(),
+      dictionary,
+      segmenter,
+      assist,
+      frequency,
+      model,
+      input_sequence: Vec::new(),
+      cursor: 0,
+      segmentation: Vec::new(),
+      unparsed: Vec::new(),
+      segmentations: Vec::new(),
+      candidates: Vec::new(),
+      uncovered: Vec::new(),
+      seen: HashSet::new(),
+      stages: BTreeMap::new(),
+    }
+  }
+}
+
+struct RankedItem {
+  text: String,
+  remain: Vec<char>,
+  // number of input keys the item leaves untouched
+  uncovered: usize,
+  match_type: MatchType,
+  consumed: usize,
+  priority: u32,
+}
+
+// words converted from the syllables so far
+#[derive(Clone)]
+struct Sentence {
+  words: Vec<String>,
+  score: f64,
+  match_type: MatchType,
+}
+
+impl PinyinContext {
+  fn segment(&mut self) {
+    // segment the longest prefix possible, keep the rest as it is
+    for len in (1..=self.input_sequence.len()).rev() {
+      let segmentations = self.segmenter.segment(&self.input_sequence[..len]);
+
+      if !segmentations.is_empty() {
+        self.segmentation = segmentations[0].clone();
+        self.unparsed = self.input_sequence[len..].to_vec();
+        self.segmentations = segmentations;
+        return;
+      }
+    }
+
+    self.segmentation = Vec::new();
+    self.unparsed = self.input_sequence.clone();
+    self.segmentations = Vec::new();
+  }
+
+  // restarts generating candidates for the current input, the sentence
+  // comes first
+  fn reset_candidates(&mut self) {
+    self.segment();
+    self.candidates.clear();
+    self.uncovered.clear();
+    self.seen.clear();
+    self.stages.clear();
+
+    for (i, segmentation) in self.segmentations.iter().enumerate() {
+      let mut uncovered = self.unparsed.len()
+        + segmentation
+          .iter()
+          .map(|segment| segment.keys.len())
+          .sum::<usize>();
+
+      for (consumed, segment) in segmentation.iter().enumerate() {
+        uncovered -= segment.keys.len();
+        self
+          .stages
+          .entry(uncovered)
+          .or_default()
+          .push((i, consumed + 1));
+      }
+    }
+
+    if let Some(sentence) = self.sentence() {
+      self.seen.insert(sentence.text.clone());
+      self.uncovered.push(self.unparsed.len());
+      self.candidates.push(sentence);
+    }
+    self.generate_assisted();
+  }
+
+  // characters leaving a few keys which start an assist code of theirs are
+  // ranked first, covering the whole input
+  fn generate_assisted(&mut self) {
+    let assist = match &self.assist {
+      Some(assist) => assist.clone(),
+      None => return,
+    };
+
+    let mut items: Vec<RankedItem> = Vec::new();
+    for (&uncovered, lookups) in self.stages.range(1..=assist.max_length()) {
+      for &(i, consumed) in lookups {
+        let keys: Vec<char> = self.segmentations[i][consumed..]
+          .iter()
+          .flat_map(|segment| segment.keys.iter())
+          .chain(self.unparsed.iter())
+          .filter(|x| **x != SEPARATOR)
+          .cloned()
+          .collect();
+
+        for mut item in self.lookup(uncovered, i, consumed) {
+          if item.text.chars().count() != 1 {
+            continue;
+          }
+          if let Some(remain) = assist.remain(&item.text, &keys) {
+            item.remain = remain;
+            item.uncovered = 0;
+            items.push(item);
+          }
+        }
+      }
+    }
+
+    self.push_ranked(items);
+  }
+
+  // words consuming `consumed` segments of the `i`th segmentation
+  fn lookup(&self, uncovered: usize, i: usize, consumed: usize) -> Vec<RankedItem> {
+    let segmentation = &self.segmentations[i];
+    let mut res: Vec<RankedItem> = Vec::new();
+
+    for item in self.dictionary.lookup(&segmentation[..consumed]) {
+      if item.consumed != consumed {
+        continue;
+      }
+
+      // shown as the codes are, without separators
+      let mut remain: Vec<char> = segmentation[item.consumed..]
+        .iter()
+        .flat_map(|segment| segment.spelling().chars().collect::<Vec<char>>())
+        .collect();
+      remain.extend(self.unparsed.iter().filter(|x| **x != SEPARATOR));
+
+      res.push(RankedItem {
+        text: item.text,
+        uncovered,
+        remain,
+        match_type: item.match_type,
+        consumed: item.consumed,
+        priority: item.priority,
+      });
+    }
+
+    res
+  }
+
+  // generates candidates leaving the fewest keys of the stages left, which
+  // rank after candidates of the previous stages
+  fn generate_stage(&mut self) {
+    let (uncovered, lookups) = match self.stages.keys().next().cloned() {
+      Some(uncovered) => (uncovered, self.stages.remove(&uncovered).unwrap()),
+      None => return,
+    };
+
+    let mut items: Vec<RankedItem> = Vec::new();
+    for (i, consumed) in lookups {
+      items.extend(self.lookup(uncovered, i, consumed));
+    }
+    self.push_ranked(items);
+  }
+
+  // appends candidates of `items` not seen yet, ranked among themselves
+  fn push_ranked(&mut self, mut items: Vec<RankedItem>) {
+    // prefer better matches, learned words, fewer syllables and higher
+    // priority
+    let frequency = self.frequency.as_ref().map(|x| x.read().unwrap());
+    items.sort_by_cached_key(|item| {
+      (
+        item.match_type,
+        Reverse(frequency.as_ref().map_or(0, |x| x.get(&item.text))),
+        item.consumed,
+        Reverse(item.priority),
+      )
+    });
+
+    for item in items {
+      if !self.seen.insert(item.text.clone()) {
+        continue;
+      }
+
+      let match_type = if item.remain.is_empty() {
+        item.match_type
+      } else {
+        max(item.match_type, MatchType::PrefixMatch)
+      };
+
+      self.uncovered.push(item.uncovered);
+      self.candidates.push(Candidate {
+        remain_codes: item.remain,
+        text: item.text,
+        match_type,
+        codes: Vec::new(),
+      });
+    }
+  }
+
+  // generates candidates until there are `count` of them or no more
+  fn generate_candidates(&mut self, count: usize) {
+    while self.candidates.len() < count && !self.stages.is_empty() {
+      self.generate_stage();
+    }
+  }
+
+  // the most likely sentence covering every syllable, by a beam search over
+  // words of the dictionary scored by the language model
+  fn sentence(&self) -> Option<Candidate> {
+    let model = self.model.as_ref()?;
+    let segments = &self.segmentation;
+    if segments.len() < 2 {
+      return None;
+    }
+
+    // sentences ending before each segment
+    let mut sentences: Vec<Vec<Sentence>> = vec![Vec::new(); segments.len() + 1];
+    sentences[0].push(Sentence {
+      words: Vec::new(),
+      score: 0.0,
+      match_type: MatchType::PerfectMatch,
+    });
+
+    for i in 0..segments.len() {
+      let mut current = mem::take(&mut sentences[i]);
+      current.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(Ordering::Equal));
+      current.truncate(SENTENCE_BEAM);
+
+      let items = self.dictionary.lookup(&segments[i..]);
+      for sentence in &current {
+        let skip = sentence.words.len().saturating_sub(2);
+        let history: Vec<&str> = sentence.words[skip..].iter().map(|x| x.as_str()).collect();
+
+        for item in &items {
+          let mut words = sentence.words.clone();
+          words.push(item.text.clone());
+
+          sentences[i + item.consumed].push(Sentence {
+            words,
+            score: sentence.score + model.score(&history, &item.text),
+            match_type: max(sentence.match_type, item.match_type),
+          });
+        }
+      }
+    }
+
+    let best = sentences[segments.len()]
+      .iter()
+      .max_by(|a, b| a.score.partial_cmp(&b.score).unwrap_or(Ordering::Equal))?;
+    // a single word is among the candidates already
+    if best.words.len() < 2 {
+      return None;
+    }
+
+    Some(Candidate {
+      remain_codes: self
+        .unparsed
+        .iter()
+        .filter(|x| **x != SEPARATOR)
+        .cloned()
+        .collect(),
+      text: best.words.concat(),
+      match_type: if self.unparsed.is_empty() {
+        best.match_type
+      } else {
+        max(best.match_type, MatchType::PrefixMatch)
+      },
+      codes: Vec::new(),
+    })
+  }
+}
+
+impl InputContext for PinyinContext {
+  fn feed(&mut self, ch: char) -> (Vec<Candidate>, Vec<String>) {
+    self.input_sequence.insert(self.cursor, ch);
+    self.cursor += 1;
+
+    self.reset_candidates();
+    info!(
+      "feed {}, input_seq: {:?}, segmentations: {}",
+      ch,
+      self.input_sequence,
+      self.segmentations.len()
+    );
+
+    (self.candidates(0, CANDIDATE_BATCH), self.codes())
+  }
+
+  fn backspace(&mut self) -> BackspaceResult {
+    if self.cursor > 0 {
+      self.cursor -= 1;
+      self.input_sequence.remove(self.cursor);
+    }
+    if self.input_sequence.len() == 0 {
+      return BackspaceResult::Cancel;
+    }
+
+    self.reset_candidates();
+    info!(
+      "backspace, input_seq: {:?}, segmentations: {}",
+      self.input_sequence,
+      self.segmentations.len()
+    );
+
+    BackspaceResult::Candidates(self.candidates(0, CANDIDATE_BATCH), self.codes())
+  }
+
+  fn confirm(&mut self, index: usize) -> Option<ConfirmResult> {
+    self.generate_candidates(index + 1);
+    let text = self.candidates.get(index)?.text.clone();
+    let uncovered = self.uncovered[index];
+    if uncovered == 0 {
+      return Some(ConfirmResult::Done(text));
+    }
+
+    // keep the keys the candidate leaves for the next selection
+    let len = self.input_sequence.len();
+    self.input_sequence.drain(..len - uncovered);
+    self.cursor = self.cursor.saturating_sub(len - uncovered);
+    self.reset_candidates();
+    info!(
+      "confirm {}, input_seq: {:?}, segmentations: {}",
+      text,
+      self.input_sequence,
+      self.segmentations.len()
+    );
+
+    Some(ConfirmResult::Partial(
+      text,
+      self.candidates(0, CANDIDATE_BATCH),
+      self.codes(),
+    ))
+  }
+
+  fn candidates(&mut self, page: usize, per_page: usize) -> Vec<Candidate> {
+    self.generate_candidates((page + 1) * per_page);
+    self
+      .candidates
+      .iter()
+      .skip(page * per_page)
+      .take(per_page)
+      .cloned()
+      .collect()
+  }
+
+  fn has_more(&mut self, page: usize, per_page: usize) -> bool {
+    self.generate_candidates((page + 1) * per_page + 1);
+    self.candidates.len() > (page + 1) * per_page
+  }
+
+  fn delete(&mut self) -> BackspaceResult {
+    if self.cursor < self.input_sequence.len() {
+      self.input_sequence.remove(self.cursor);
+    }
+    if self.input_sequence.len() == 0 {
+      return BackspaceResult::Cancel;
+    }
+
+    self.reset_candidates();
+    info!(
+      "delete, input_seq: {:?}, segmentations: {}",
+      self.input_sequence,
+      self.segmentations.len()
+    );
+
+    BackspaceResult::Candidates(self.candidates(0, CANDIDATE_BATCH), self.codes())
+  }
+
+  fn move_cursor(&mut self, motion: CursorMotion) {
+    self.cursor = match motion {
+      CursorMotion::Left => self.cursor.saturating_sub(1),
+      CursorMotion::Right => min(self.cursor + 1, self.input_sequence.len()),
+      CursorMotion::Start => 0,
+      CursorMotion::End => self.input_sequence.len(),
+    };
+  }
+
+  fn cursor(&self) -> usize {
+    let shown = |keys: &[char]| keys.iter().filter(|x| **x != SEPARATOR).count();
+    let mut keys = self.cursor;
+    let mut res = 0;
+
+    for segment in &self.segmentation {
+      if keys <= segment.keys.len() {
+        return res + shown(&segment.keys[..keys]);
+      }
+
+      keys -= segment.keys.len();
+      // the segment and the separator after it
+      res += shown(&segment.keys) + 1;
+    }
+
+    res + keys
+  }
+
+  fn id(&self) -> ContextId {
+    self.id.clone()
+  }
+
+  // a tone follows a syllable, not a separator or another tone
+  fn accepts_tone(&self, key: char) -> bool {
+    if self.segmenter.tone(key).is_none() || self.cursor == 0 {
+      return false;
+    }
+
+    let prev = self.input_sequence[self.cursor - 1];
+    prev != SEPARATOR && self.segmenter.tone(prev).is_none()
+  }
+
+  fn codes(&self) -> Vec<String> {
+    let mut res: Vec<String> = self
+      .segmentation
+      .iter()
+      .map(|segment| segment.spelling())
+      .collect();
+
+    if !self.unparsed.is_empty() {
+      res.push(self.unparsed.iter().collect());
+    }
+
+    res
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::engine::pinyin::{FuzzySyllables, Syllable, WordEntry};
+
+  fn context(words: &[(&str, &str)]) -> PinyinContext {
+    let segmenter = Segmenter::new(None, FuzzySyllables::new(vec![]).unwrap(), vec![]);
+    let mut dictionary = PinyinDictionary::new();
+    for (text, pinyin) in words {
+      let syllables: Vec<Syllable> = pinyin
+        .split(' ')
+        .map(|x| segmenter.table().parse(x).unwrap())
+        .collect();
+      dictionary.insert(
+        &syllables,
+        WordEntry {
+          text: text.to_string(),
+          priority: 100,
+          tones: vec![None; syllables.len()],
+        },
+      );
+    }
+
+    PinyinContext::new(Arc::new(dictionary), Arc::new(segmenter), None, None, None)
+  }
+
+  fn texts(candidates: &[Candidate]) -> Vec<String> {
+    candidates.iter().map(|x| x.text.clone()).collect()
+  }
+
+  #[test]
+  fn candidates_on_demand() {
+    let mut ctx = context(&[
+      ("西", "xi"),
+      ("西安", "xi an"),
+      ("先", "xian"),
+      ("希", "xi"),
+    ]);
+    for ch in "xian".chars() {
+      ctx.feed(ch);
+    }
+
+    // words covering the whole input come before those leaving keys, which
+    // aren't looked up until asked for
+    ctx.reset_candidates();
+    assert_eq!(texts(&ctx.candidates(0, 2)), vec!["先", "西安"]);
+    assert!(!ctx.stages.is_empty());
+    assert!(ctx.has_more(0, 2));
+    assert_eq!(texts(&ctx.candidates(1, 2)), vec!["西", "希"]);
+    assert_eq!(ctx.candidates(1, 2)[0].remain_codes, vec!['a', 'n']);
+    assert!(!ctx.has_more(1, 2));
+  }
+
+  #[test]
+  fn remain_codes_without_separators() {
+    let mut ctx = context(&[("西", "xi"), ("安", "an"), ("西安", "xi an")]);
+    for ch in "xi'an".chars() {
+      ctx.feed(ch);
+    }
+
+    let candidates = ctx.candidates(0, 10);
+    let xi = candidates.iter().find(|x| x.text == "西").unwrap();
+    assert_eq!(xi.remain_codes, vec!['a', 'n']);
+    assert_eq!(ctx.codes(), vec!["xi", "an"]);
+
+    // the rest of the input keeps its separator
+    let index = candidates.iter().position(|x| x.text == "西").unwrap();
+    match ctx.confirm(index) {
+      Some(ConfirmResult::Partial(_, candidates, codes)) => {
+        assert_eq!(candidates[0].text, "安");
+        assert_eq!(codes, vec!["an"]);
+      }
+      res => panic!("unexpected {:?}", res),
+    }
+  }
+
+  #[test]
+  fn cursor_in_shown_codes() {
+    let mut ctx = context(&[("西", "xi"), ("安", "an")]);
+    for ch in "xi'an".chars() {
+      ctx.feed(ch);
+    }
+
+    // shown as `xi an`, the separator typed isn't shown twice
+    assert_eq!(ctx.cursor(), 5);
+    ctx.move_cursor(CursorMotion::Left);
+    assert_eq!(ctx.cursor(), 4);
+    ctx.move_cursor(CursorMotion::Left);
+    ctx.move_cursor(CursorMotion::Left);
+    assert_eq!(ctx.cursor(), 2);
+    ctx.move_cursor(CursorMotion::Start);
+    assert_eq!(ctx.cursor(), 0);
+  }
+
+  #[test]
+  fn assist_codes_rank_characters_first() {
+    let mut ctx = context(&[
+      ("中", "zhong"),
+      ("钟", "zhong"),
+      ("种", "zhong"),
+      ("中间", "zhong jian"),
+    ]);
+    ctx.assist = Some(Arc::new(AssistCode::parse("钟\tjk\n中\tkk\n".as_bytes())));
+
+    for ch in "zhongj".chars() {
+      ctx.feed(ch);
+    }
+    let candidates = ctx.candidates(0, 10);
+    assert_eq!(texts(&candidates[..2]), vec!["钟", "中间"]);
+    assert_eq!(candidates[0].remain_codes, vec!['k']);
+    assert_eq!(candidates.len(), 4);
+
+    ctx.feed('k');
+    let candidates = ctx.candidates(0, 10);
+    assert_eq!(candidates[0].text, "钟");
+    assert_eq!(candidates[0].match_type, MatchType::PerfectMatch);
+    match ctx.confirm(0) {
+      Some(ConfirmResult::Done(text)) => assert_eq!(text, "钟"),
+      res => panic!("unexpected {:?}", res),
+    }
+  }
+
+  #[test]
+  fn sentence_of_the_highest_score() {
+    let mut ctx = context(&[
+      ("中", "zhong"),
+      ("钟", "zhong"),
+      ("国", "guo"),
+      ("过", "guo"),
+    ]);
+    let unigrams = "中\t5\n钟\t5\n国\t5\n过\t5\n";
+    let sentence = |ctx: &mut PinyinContext, bigrams: &str| {
+      let content = format!("{}{}", unigrams, bigrams);
+      ctx.model = Some(Arc::new(
+        LanguageModel::parse("test.lm", content.as_bytes()).unwrap(),
+      ));
+      ctx.reset_candidates();
+      ctx.candidates(0, 1)[0].text.clone()
+    };
+    for ch in "zhongguo".chars() {
+      ctx.feed(ch);
+    }
+
+    assert_eq!(sentence(&mut ctx, "钟 过\t3\n中 国\t1\n"), "钟过");
+    assert_eq!(sentence(&mut ctx, "钟 过\t3\n中 国\t4\n"), "中国");
+  }
+}
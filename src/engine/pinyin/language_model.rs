@@ -0,0 +1,150 @@
+use crate::path::LocalDataPath;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+// weight of a shorter history when the longer one is never seen
+static BACKOFF: f64 = 0.4;
+
+/// N-gram counts of words, up to trigrams, scored with stupid backoff.
+pub struct LanguageModel {
+  // words of each n-gram joined by spaces
+  counts: HashMap<String, u64>,
+  // sum of unigram counts
+  total: u64,
+}
+
+impl LanguageModel {
+  /// Loads `filename` from the pinyin data directory, each line formatted as
+  /// `words<TAB>count`, words of bigrams and trigrams separated by spaces.
+  pub fn load(filename: &str) -> Result<LanguageModel, String> {
+    let filepath = LocalDataPath::new().sub("pinyin").file(filename);
+
+    let file = File::open(&filepath).map_err(|e| {
+      format!(
+        "failed to open language model {}: {}",
+        filepath.display(),
+        e
+      )
+    })?;
+
+    LanguageModel::parse(filename, BufReader::new(file))
+  }
+
+  /// Parses n-gram counts as `load` does, `filename` is only for errors.
+  pub fn parse<R: BufRead>(filename: &str, reader: R) -> Result<LanguageModel, String> {
+    let mut res = LanguageModel {
+      counts: HashMap::new(),
+      total: 0,
+    };
+    for (lineno, l) in reader.lines().enumerate() {
+      let line = l.map_err(|e| format!("{}:{}: {}", filename, lineno + 1, e))?;
+      let line = line.trim();
+      if line.is_empty() {
+        continue;
+      }
+
+      let v: Vec<&str> = line.split('\t').collect();
+      if v.len() != 2 {
+        return Err(format!(
+          "{}:{}: expect words and count separated by tab",
+          filename,
+          lineno + 1
+        ));
+      }
+      let words: Vec<&str> = v[0].split(' ').filter(|x| !x.is_empty()).collect();
+      if words.is_empty() || words.len() > 3 {
+        return Err(format!("{}:{}: expect 1 to 3 words", filename, lineno + 1));
+      }
+      let count = v[1]
+        .parse::<u64>()
+        .map_err(|_| format!("{}:{}: invalid count '{}'", filename, lineno + 1, v[1]))?;
+
+      if words.len() == 1 {
+        res.total += count;
+      }
+      *res.counts.entry(words.join(" ")).or_insert(0) += count;
+    }
+
+    Ok(res)
+  }
+
+  fn count(&self, words: &[&str]) -> u64 {
+    self.counts.get(&words.join(" ")).cloned().unwrap_or(0)
+  }
+
+  /// Log probability of `word` following `history`, backing off to shorter
+  /// histories the model hasn't seen `word` after.
+  pub fn score(&self, history: &[&str], word: &str) -> f64 {
+    let mut penalty = 0.0;
+
+    for start in 0..history.len() {
+      let context = &history[start..];
+      let mut ngram = context.to_vec();
+      ngram.push(word);
+
+      let (count, context_count) = (self.count(&ngram), self.count(context));
+      if count > 0 && context_count > 0 {
+        return penalty + (count as f64 / context_count as f64).ln();
+      }
+      penalty += BACKOFF.ln();
+    }
+
+    // unknown words are as likely as a word seen once
+    let count = std::cmp::max(self.count(&[word]), 1);
+    penalty + (count as f64 / (self.total + 1) as f64).ln()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn model() -> LanguageModel {
+    let content = "中国\t6\n人\t3\n\n中国 人\t2\n中国 人 民\t1\n人 民\t1\n民\t1\n";
+    LanguageModel::parse("test.lm", content.as_bytes()).unwrap()
+  }
+
+  #[test]
+  fn parse() {
+    let model = model();
+    assert_eq!(model.total, 10);
+    assert_eq!(model.count(&["中国", "人"]), 2);
+    assert_eq!(model.count(&["中国", "人", "民"]), 1);
+
+    let error = |content: &str| {
+      LanguageModel::parse("test.lm", content.as_bytes())
+        .err()
+        .unwrap()
+    };
+    assert_eq!(
+      error("中国\n"),
+      "test.lm:1: expect words and count separated by tab"
+    );
+    assert_eq!(error("中 国 人 民\t1\n"), "test.lm:1: expect 1 to 3 words");
+    assert_eq!(error("中国\t1\n人\tx\n"), "test.lm:2: invalid count 'x'");
+  }
+
+  #[test]
+  fn stupid_backoff() {
+    let model = model();
+    let close = |a: f64, b: f64| (a - b).abs() < 1e-9;
+
+    assert!(close(
+      model.score(&["中国", "人"], "民"),
+      (1.0f64 / 2.0).ln()
+    ));
+    assert!(close(model.score(&["人"], "民"), (1.0f64 / 3.0).ln()));
+    // 国 人 is never seen, backs off to 人 民
+    assert!(close(
+      model.score(&["国", "人"], "民"),
+      BACKOFF.ln() + (1.0f64 / 3.0).ln()
+    ));
+    assert!(close(
+      model.score(&["民"], "中国"),
+      BACKOFF.ln() + (6.0f64 / 11.0).ln()
+    ));
+    // unknown words count once
+    assert!(close(model.score(&[], "国"), (1.0f64 / 11.0).ln()));
+  }
+}
@@ -1,21 +1,135 @@
 mod assist_code;
 mod codes;
+mod dictionary;
+mod input_context;
+mod language_model;
 mod pinyin;
 mod scheme;
+mod segment;
+mod syllable;
 
 pub use assist_code::AssistCode;
 pub use codes::{PinyinCode, PinyinFinals, PinyinInitials};
+pub use dictionary::{PinyinDictionary, WordEntry};
+pub use input_context::PinyinContext;
+pub use language_model::LanguageModel;
 pub use pinyin::PinyinEngine;
 pub use scheme::Scheme;
+pub use segment::Segmenter;
+pub use syllable::Syllable;
 
-struct FuzzySyllables(Vec<(PinyinCode, PinyinCode)>);
+use syllable::SyllableTable;
+
+pub struct FuzzySyllables(Vec<(PinyinCode, PinyinCode)>);
 
 impl FuzzySyllables {
-  pub fn new(v: Vec<(String, String)>) -> FuzzySyllables {
-    FuzzySyllables(
-      v.iter()
-        .map(|(a, b)| (PinyinCode::from(a), PinyinCode::from(b)))
+  pub fn new(v: Vec<(String, String)>) -> Result<FuzzySyllables, String> {
+    let parse =
+      |s: &String| PinyinCode::parse(s).ok_or_else(|| format!("unknown fuzzy syllable '{}'", s));
+
+    let mut res = Vec::new();
+    for (a, b) in &v {
+      let pair = (parse(a)?, parse(b)?);
+      match pair {
+        (PinyinCode::Initials(_), PinyinCode::Initials(_))
+        | (PinyinCode::Finals(_), PinyinCode::Finals(_)) => res.push(pair),
+        _ => {
+          return Err(format!(
+            "fuzzy syllables '{}' and '{}' should be both initials or both finals",
+            a, b
+          ))
+        }
+      }
+    }
+
+    Ok(FuzzySyllables(res))
+  }
+
+  // codes fuzzy with `code`, pairs work in both directions
+  fn alternatives(&self, code: PinyinCode) -> Vec<PinyinCode> {
+    let mut res = vec![code];
+
+    for (a, b) in &self.0 {
+      if *a == code && !res.contains(b) {
+        res.push(*b);
+      } else if *b == code && !res.contains(a) {
+        res.push(*a);
+      }
+    }
+
+    res
+  }
+
+  /// Valid syllables `syllable` may be mistaken for, `syllable` excluded.
+  pub fn expand(&self, syllable: &Syllable, table: &SyllableTable) -> Vec<Syllable> {
+    let initials: Vec<Option<PinyinInitials>> = match syllable.initial {
+      Some(initial) => self
+        .alternatives(PinyinCode::Initials(initial))
+        .into_iter()
+        .filter_map(|code| match code {
+          PinyinCode::Initials(x) => Some(Some(x)),
+          _ => None,
+        })
         .collect(),
-    )
+      None => vec![None],
+    };
+    let finals: Vec<PinyinFinals> = self
+      .alternatives(PinyinCode::Finals(syllable.finals))
+      .into_iter()
+      .filter_map(|code| match code {
+        PinyinCode::Finals(x) => Some(x),
+        _ => None,
+      })
+      .collect();
+
+    let mut res = Vec::new();
+    for initial in &initials {
+      for final_code in &finals {
+        if let Some(x) = table.syllable(*initial, *final_code) {
+          if x != *syllable {
+            res.push(x);
+          }
+        }
+      }
+    }
+
+    res
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn pair(a: &str, b: &str) -> Vec<(String, String)> {
+    vec![(a.to_string(), b.to_string())]
+  }
+
+  #[test]
+  fn invalid_fuzzy_syllables() {
+    assert!(FuzzySyllables::new(pair("zz", "zh")).is_err());
+    assert!(FuzzySyllables::new(pair("z", "ang")).is_err());
+    assert!(FuzzySyllables::new(pair("Z", "zh")).is_ok());
+  }
+
+  #[test]
+  fn expand_both_directions() {
+    let table = SyllableTable::new();
+    let fuzzy = FuzzySyllables::new(vec![
+      ("z".to_string(), "zh".to_string()),
+      ("an".to_string(), "ang".to_string()),
+    ])
+    .unwrap();
+
+    let mut res: Vec<String> = fuzzy
+      .expand(&table.parse("zan").unwrap(), &table)
+      .iter()
+      .map(|x| x.spelling())
+      .collect();
+    res.sort();
+    assert_eq!(res, vec!["zang", "zhan", "zhang"]);
+
+    let res = fuzzy.expand(&table.parse("zhang").unwrap(), &table);
+    assert_eq!(res.len(), 3);
   }
 }
@@ -1,14 +1,164 @@
-use crate::engine::candidate::Candidate;
-use crate::engine::engine::IMEngine;
+use super::assist_code::AssistCode;
+use super::dictionary::PinyinDictionary;
+use super::input_context::PinyinContext;
+use super::language_model::LanguageModel;
+use super::scheme::Scheme;
+use super::segment::Segmenter;
+use super::FuzzySyllables;
+use crate::engine::engine::{IMEngine, InputContext};
+use crate::engine::frequency::WordFrequency;
+use crate::engine::Configuration;
+use async_std::sync::Mutex;
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::rc::Rc;
+use std::sync::{Arc, RwLock};
 
 pub struct PinyinEngine {
+  dictionary: Arc<PinyinDictionary>,
+  segmenter: Arc<Segmenter>,
+  assist: Option<Arc<AssistCode>>,
+  // learned frequencies, if dynamic word frequency is enabled
+  frequency: Option<Arc<RwLock<WordFrequency>>>,
+  model: Option<Arc<LanguageModel>>,
 }
 
-// impl IMEngine for PinyinEngine {
-//   fn start_context(&self) -> Rc<RefCell<dyn InputContext>>;
-//
-//   fn start_context_async(&self) -> Arc<Mutex<dyn InputContext>>;
-//
-//   fn keycodes(&self) -> HashSet<char>;
-// }
-//
+impl IMEngine for PinyinEngine {
+  fn start_context(&self) -> Rc<RefCell<dyn InputContext>> {
+    Rc::new(RefCell::new(self.context()))
+  }
+
+  fn start_context_async(&self) -> Arc<Mutex<dyn InputContext>> {
+    Arc::new(Mutex::new(self.context()))
+  }
+
+  fn new_context(&self) -> Box<dyn InputContext> {
+    Box::new(self.context())
+  }
+
+  fn keycodes(&self) -> HashSet<char> {
+    let mut res = self.segmenter.keycodes();
+
+    if let Some(assist) = &self.assist {
+      res.extend(assist.keycodes());
+    }
+
+    res
+  }
+
+  fn tone_keys(&self) -> HashSet<char> {
+    self.segmenter.tone_keys()
+  }
+
+  fn separators(&self) -> HashSet<char> {
+    self.segmenter.separators()
+  }
+
+  fn commit(&mut self, text: &str) {
+    if let Some(frequency) = &self.frequency {
+      frequency.write().unwrap().learn(text);
+    }
+  }
+
+  fn flush(&mut self) {
+    if let Some(frequency) = &self.frequency {
+      frequency.write().unwrap().flush();
+    }
+  }
+}
+
+impl PinyinEngine {
+  pub fn new(config: Configuration) -> Result<PinyinEngine, String> {
+    if let Configuration::Pinyin {
+      scheme_file,
+      assist_file,
+      fuzzy_syllables,
+      character_database,
+      word_database,
+      dynamic_word_frequency,
+      language_model,
+      tone_keys,
+    } = config
+    {
+      let frequency_file = format!("{}.freq", character_database);
+      let mut databases = vec![character_database];
+      databases.extend(word_database);
+
+      let mut res = PinyinEngine::database_files(
+        scheme_file
+          .map(|filename| Scheme::new(&filename))
+          .transpose()?,
+        FuzzySyllables::new(fuzzy_syllables)?,
+        tone_keys.unwrap_or_default().chars().collect(),
+        assist_file
+          .map(|filename| AssistCode::new(&filename))
+          .transpose()?,
+        &databases,
+      )?;
+      if dynamic_word_frequency {
+        res.frequency = Some(WordFrequency::shared(&frequency_file));
+      }
+      if let Some(filename) = language_model {
+        res.model = Some(Arc::new(LanguageModel::load(&filename)?));
+      }
+      Ok(res)
+    } else {
+      Err("expect pinyin configuration".to_string())
+    }
+  }
+
+  pub fn context(&self) -> PinyinContext {
+    PinyinContext::new(
+      self.dictionary.clone(),
+      self.segmenter.clone(),
+      self.assist.clone(),
+      self.frequency.clone(),
+      self.model.clone(),
+    )
+  }
+
+  pub fn database_files(
+    scheme: Option<Scheme>,
+    fuzzy: FuzzySyllables,
+    tone_keys: Vec<char>,
+    assist: Option<AssistCode>,
+    filenames: &[String],
+  ) -> Result<PinyinEngine, String> {
+    let segmenter = Segmenter::new(scheme, fuzzy, tone_keys.clone());
+
+    if !tone_keys.is_empty() {
+      if tone_keys.len() != 5 {
+        return Err("expect 5 tone keys, of tone 1 to 5".to_string());
+      }
+
+      let mut keycodes = segmenter.keycodes();
+      if let Some(assist) = &assist {
+        keycodes.extend(assist.keycodes());
+      }
+      for (i, key) in tone_keys.iter().enumerate() {
+        if keycodes.contains(key) || tone_keys[..i].contains(key) {
+          return Err(format!("tone key '{}' is used by another key", key));
+        }
+      }
+    }
+
+    let dictionary = PinyinDictionary::files(filenames, segmenter.table())?;
+
+    Ok(PinyinEngine::dictionary(dictionary, segmenter, assist))
+  }
+
+  /// An engine looking up words of `dictionary`.
+  pub fn dictionary(
+    dictionary: PinyinDictionary,
+    segmenter: Segmenter,
+    assist: Option<AssistCode>,
+  ) -> PinyinEngine {
+    PinyinEngine {
+      dictionary: Arc::new(dictionary),
+      segmenter: Arc::new(segmenter),
+      assist: assist.map(Arc::new),
+      frequency: None,
+      model: None,
+    }
+  }
+}
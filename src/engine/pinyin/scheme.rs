@@ -1,4 +1,7 @@
 use super::codes::{PinyinCode, PinyinFinals, PinyinInitials};
+use super::segment::{Segment, Segmentation};
+use super::syllable::{Syllable, SyllableTable};
+use crate::engine::candidate::MatchType;
 use crate::path::LocalDataPath;
 use plist;
 use serde::{Deserialize, Serialize};
@@ -21,20 +24,22 @@ struct KeyMapProtocol {
 }
 
 impl Scheme {
-  pub fn new(filename: &str) -> Scheme {
+  pub fn new(filename: &str) -> Result<Scheme, String> {
     let filepath = LocalDataPath::new().sub("keymap").file(filename);
 
-    let keymap: KeyMapProtocol = plist::from_file(filepath.as_path()).expect("parse plit failed");
+    let keymap: KeyMapProtocol = plist::from_file(filepath.as_path())
+      .map_err(|e| format!("failed to parse keymap {}: {}", filepath.display(), e))?;
 
     let (initials, finals, special) =
-      Scheme::parse_keymap(keymap.Sheng, keymap.Yun, keymap.Special);
+      Scheme::parse_keymap(keymap.Sheng, keymap.Yun, keymap.Special)
+        .map_err(|e| format!("invalid keymap {}: {}", filepath.display(), e))?;
 
-    Scheme {
+    Ok(Scheme {
       initials: Arc::new(initials),
       finals: Arc::new(finals),
       special: Arc::new(special),
       version: keymap.Vesion,
-    }
+    })
   }
 
   pub fn keycodes(&self) -> HashSet<char> {
@@ -63,39 +68,282 @@ impl Scheme {
     sheng: HashMap<String, String>,
     yun: HashMap<String, String>,
     special: HashMap<String, String>,
-  ) -> (
-    HashMap<String, Vec<PinyinInitials>>,
-    HashMap<String, Vec<PinyinFinals>>,
-    HashMap<String, Vec<PinyinCode>>,
-  ) {
+  ) -> Result<
+    (
+      HashMap<String, Vec<PinyinInitials>>,
+      HashMap<String, Vec<PinyinFinals>>,
+      HashMap<String, Vec<PinyinCode>>,
+    ),
+    String,
+  > {
     let mut initials = HashMap::<String, Vec<PinyinInitials>>::new();
     let mut finals = HashMap::<String, Vec<PinyinFinals>>::new();
     let mut codes = HashMap::<String, Vec<PinyinCode>>::new();
 
     for (keycode, value) in sheng {
-      if let Some(_x) = Scheme::parse_pinyin_code(&value) {
-        for x in _x {
-          match x {
-            PinyinCode::Initials(initial_code) => {
-              initials.entry(keycode.clone()).or_default().push(initial_code);
-            }
-            PinyinCode::Finals(final_code) => {
-              finals.entry(keycode.clone()).or_default().push(final_code);
-            }
+      for x in Scheme::parse_pinyin_code("Sheng", &keycode, &value)? {
+        match x {
+          PinyinCode::Initials(initial_code) => {
+            initials
+              .entry(keycode.clone())
+              .or_default()
+              .push(initial_code);
+          }
+          PinyinCode::Finals(final_code) => {
+            finals.entry(keycode.clone()).or_default().push(final_code);
+          }
+        }
+      }
+    }
+
+    for (keycode, value) in yun {
+      for x in Scheme::parse_pinyin_code("Yun", &keycode, &value)? {
+        match x {
+          PinyinCode::Finals(final_code) => {
+            finals.entry(keycode.clone()).or_default().push(final_code);
+          }
+          PinyinCode::Initials(initial_code) => {
+            return Err(format!(
+              "'{}' in '{}' of key '{}' in Yun is not a final",
+              initial_code.as_str(),
+              value,
+              keycode
+            ));
           }
         }
       }
     }
 
-    (initials, finals, codes)
+    for (keycode, value) in special {
+      let x = Scheme::parse_pinyin_code("Special", &keycode, &value)?;
+      codes.entry(keycode).or_default().extend(x);
+    }
+
+    Ok((initials, finals, codes))
   }
 
-  fn parse_pinyin_code(code: &str) -> Option<Vec<PinyinCode>> {
-    let mut res: Vec<PinyinCode> = Vec::new();
-    for part in code.split("|").collect::<Vec<&str>>() {
-      res.push(PinyinCode::from(part.to_string()));
+  // every part separated by `|` should be pinyin, `section` and `key` name
+  // the entry of the keymap
+  fn parse_pinyin_code(section: &str, key: &str, code: &str) -> Result<Vec<PinyinCode>, String> {
+    code
+      .split('|')
+      .map(|x| {
+        PinyinCode::parse(x).ok_or_else(|| {
+          format!(
+            "unknown pinyin '{}' in '{}' of key '{}' in {}",
+            x, code, key, section
+          )
+        })
+      })
+      .collect()
+  }
+
+  /// Decodes `keys` two by two into syllables, a trailing single key matches
+  /// every syllable starting with the initials it stands for.
+  pub fn decode(&self, keys: &[char], table: &SyllableTable) -> Option<Segmentation> {
+    let mut res: Segmentation = Vec::new();
+
+    for pair in keys.chunks(2) {
+      let syllables = if pair.len() == 2 {
+        self.decode_pair(pair[0], pair[1], table)
+      } else {
+        self.decode_single(pair[0], table)
+      };
+
+      if syllables.is_empty() {
+        return None;
+      }
+      res.push(Segment {
+        keys: pair.to_vec(),
+        syllables,
+        tone: None,
+      });
     }
 
     Some(res)
   }
+
+  fn decode_pair(
+    &self,
+    first: char,
+    second: char,
+    table: &SyllableTable,
+  ) -> Vec<(Syllable, MatchType)> {
+    let mut res: Vec<(Syllable, MatchType)> = Vec::new();
+
+    if let (Some(initials), Some(finals)) = (
+      self.initials.get(&first.to_string()),
+      self.finals.get(&second.to_string()),
+    ) {
+      for initial in initials {
+        for final_code in finals {
+          if let Some(syllable) = table.syllable(Some(*initial), *final_code) {
+            res.push((syllable, MatchType::PerfectMatch));
+          }
+        }
+      }
+    }
+
+    // zero-initial syllables
+    if let Some(codes) = self.special.get(&format!("{}{}", first, second)) {
+      for code in codes {
+        if let PinyinCode::Finals(final_code) = code {
+          if let Some(syllable) = table.syllable(None, *final_code) {
+            res.push((syllable, MatchType::PerfectMatch));
+          }
+        }
+      }
+    }
+
+    res
+  }
+
+  fn decode_single(&self, key: char, table: &SyllableTable) -> Vec<(Syllable, MatchType)> {
+    let mut res: Vec<(Syllable, MatchType)> = Vec::new();
+
+    if let Some(initials) = self.initials.get(&key.to_string()) {
+      for initial in initials {
+        for syllable in table.completions(initial.as_str()) {
+          if syllable.initial == Some(*initial) {
+            res.push((syllable, MatchType::PrefixMatch));
+          }
+        }
+      }
+    }
+
+    for (keycode, codes) in self.special.as_ref() {
+      if keycode.starts_with(key) {
+        for code in codes {
+          if let PinyinCode::Finals(final_code) = code {
+            if let Some(syllable) = table.syllable(None, *final_code) {
+              res.push((syllable, MatchType::PrefixMatch));
+            }
+          }
+        }
+      }
+    }
+
+    res
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn keymap(entries: &[(&str, &str)]) -> HashMap<String, String> {
+    entries
+      .iter()
+      .map(|(k, v)| (k.to_string(), v.to_string()))
+      .collect()
+  }
+
+  #[test]
+  fn parse_keymap() {
+    let (initials, finals, _) = Scheme::parse_keymap(
+      keymap(&[("v", "zh"), ("o", "o")]),
+      keymap(&[("s", "iong|ong")]),
+      HashMap::new(),
+    )
+    .unwrap();
+
+    assert_eq!(initials["v"].len(), 1);
+    assert_eq!(finals["s"].len(), 2);
+    assert_eq!(finals["o"].len(), 1);
+  }
+
+  #[test]
+  fn unknown_pinyin() {
+    let res = Scheme::parse_keymap(
+      HashMap::new(),
+      keymap(&[("s", "iong|ongx")]),
+      HashMap::new(),
+    );
+
+    assert_eq!(
+      res.err().unwrap(),
+      "unknown pinyin 'ongx' in 'iong|ongx' of key 's' in Yun"
+    );
+  }
+
+  #[test]
+  fn initials_in_yun() {
+    let res = Scheme::parse_keymap(HashMap::new(), keymap(&[("s", "ong|zh")]), HashMap::new());
+
+    assert_eq!(
+      res.err().unwrap(),
+      "'zh' in 'ong|zh' of key 's' in Yun is not a final"
+    );
+  }
+
+  // part of the xiaohe scheme
+  fn scheme() -> Scheme {
+    let (initials, finals, special) = Scheme::parse_keymap(
+      keymap(&[("v", "zh"), ("g", "g"), ("l", "l"), ("n", "n")]),
+      keymap(&[
+        ("s", "iong|ong"),
+        ("o", "uo|o"),
+        ("v", "ui|v"),
+        ("t", "ue|ve"),
+      ]),
+      keymap(&[("aa", "a"), ("an", "an"), ("oo", "o")]),
+    )
+    .unwrap();
+
+    Scheme {
+      initials: Arc::new(initials),
+      finals: Arc::new(finals),
+      special: Arc::new(special),
+      version: String::new(),
+    }
+  }
+
+  fn spellings(syllables: &[(Syllable, MatchType)]) -> Vec<String> {
+    let mut res: Vec<String> = syllables.iter().map(|x| x.0.spelling()).collect();
+    res.sort();
+    res
+  }
+
+  #[test]
+  fn decode_pair() {
+    let (scheme, table) = (scheme(), SyllableTable::new());
+
+    assert_eq!(spellings(&scheme.decode_pair('v', 's', &table)), ["zhong"]);
+    assert_eq!(spellings(&scheme.decode_pair('g', 'o', &table)), ["guo"]);
+    // ü is written as v
+    assert_eq!(spellings(&scheme.decode_pair('l', 'v', &table)), ["lv"]);
+    assert_eq!(spellings(&scheme.decode_pair('n', 't', &table)), ["nve"]);
+    assert!(scheme.decode_pair('v', 'x', &table).is_empty());
+
+    // zero-initial syllables are spelled out
+    let syllables = scheme.decode_pair('a', 'n', &table);
+    assert_eq!(spellings(&syllables), ["an"]);
+    assert_eq!(syllables[0].0.initial, None);
+    assert_eq!(syllables[0].1, MatchType::PerfectMatch);
+  }
+
+  #[test]
+  fn decode_single() {
+    let (scheme, table) = (scheme(), SyllableTable::new());
+
+    let syllables = scheme.decode_single('v', &table);
+    assert!(spellings(&syllables).contains(&"zhong".to_string()));
+    assert!(syllables
+      .iter()
+      .all(|x| x.0.spelling().starts_with("zh") && x.1 == MatchType::PrefixMatch));
+    assert_eq!(spellings(&scheme.decode_single('a', &table)), ["a", "an"]);
+    assert!(scheme.decode_single('x', &table).is_empty());
+  }
+
+  #[test]
+  fn decode() {
+    let (scheme, table) = (scheme(), SyllableTable::new());
+
+    let segments = scheme.decode(&['v', 's', 'g', 'o', 'l'], &table).unwrap();
+    assert_eq!(segments.len(), 3);
+    assert_eq!(segments[1].keys, vec!['g', 'o']);
+    assert_eq!(spellings(&segments[1].syllables), ["guo"]);
+    assert_eq!(segments[2].syllables[0].1, MatchType::PrefixMatch);
+    assert!(scheme.decode(&['v', 's', 'v', 'x'], &table).is_none());
+  }
 }
@@ -0,0 +1,438 @@
+use super::codes::PinyinInitials;
+use super::scheme::Scheme;
+use super::syllable::{Syllable, SyllableTable};
+use super::FuzzySyllables;
+use crate::engine::candidate::MatchType;
+use std::cmp::max;
+use std::collections::HashSet;
+
+static MAX_SEGMENTATIONS: usize = 8;
+/// Key ending a syllable explicitly, e.g. `xi'an`.
+pub static SEPARATOR: char = '\'';
+
+/// A span of raw keys, decoded into one or more syllables it may stand for.
+/// Separators typed before the span and the tone key after it are part of
+/// its keys.
+#[derive(Clone, Debug)]
+pub struct Segment {
+  pub keys: Vec<char>,
+  pub syllables: Vec<(Syllable, MatchType)>,
+  // tone typed after the syllable, 1 to 5
+  pub tone: Option<u8>,
+}
+
+impl Segment {
+  pub fn spelling(&self) -> String {
+    let mut res: String = self.keys.iter().filter(|x| **x != SEPARATOR).collect();
+
+    // the tone key is shown as the tone number
+    if let Some(tone) = self.tone {
+      res.pop();
+      res.push_str(&tone.to_string());
+    }
+
+    res
+  }
+}
+
+pub type Segmentation = Vec<Segment>;
+
+pub struct Segmenter {
+  table: SyllableTable,
+  // shuangpin scheme, full pinyin if not set
+  scheme: Option<Scheme>,
+  fuzzy: FuzzySyllables,
+  // keys of tone 1 to 5, typed after a syllable
+  tone_keys: Vec<char>,
+}
+
+impl Segmenter {
+  pub fn new(scheme: Option<Scheme>, fuzzy: FuzzySyllables, tone_keys: Vec<char>) -> Segmenter {
+    Segmenter {
+      table: SyllableTable::new(),
+      scheme,
+      fuzzy,
+      tone_keys,
+    }
+  }
+
+  pub fn table(&self) -> &SyllableTable {
+    &self.table
+  }
+
+  pub fn keycodes(&self) -> HashSet<char> {
+    let mut res: HashSet<char> = match &self.scheme {
+      Some(scheme) => scheme.keycodes(),
+      None => ('a'..='z').collect(),
+    };
+    res.insert(SEPARATOR);
+
+    res
+  }
+
+  /// Keys separating syllables, which are input only after a syllable.
+  pub fn separators(&self) -> HashSet<char> {
+    vec![SEPARATOR].into_iter().collect()
+  }
+
+  /// Keys of tones, which are input only after a syllable.
+  pub fn tone_keys(&self) -> HashSet<char> {
+    self.tone_keys.iter().cloned().collect()
+  }
+
+  pub fn tone(&self, key: char) -> Option<u8> {
+    let i = self.tone_keys.iter().position(|x| *x == key)?;
+    Some(i as u8 + 1)
+  }
+
+  fn is_boundary(&self, key: char) -> bool {
+    key == SEPARATOR || self.tone(key).is_some()
+  }
+
+  /// Splits `input` into syllables, returning every way to do so (up to
+  /// `MAX_SEGMENTATIONS`), those with fewer syllables first. The last segment
+  /// may be an incomplete syllable, matching every syllable it prefixes,
+  /// and an initial alone anywhere matches every syllable it starts (简拼).
+  ///
+  /// With a shuangpin scheme, keys are decoded in pairs instead. Keys
+  /// between separators and tone keys are segmented on their own, a tone key
+  /// sets the tone of the segment before it. Each segment is then expanded to
+  /// its fuzzy syllables, tagged with `FuzzyMatch`.
+  pub fn segment(&self, input: &[char]) -> Vec<Segmentation> {
+    let mut res: Vec<Segmentation> = vec![Vec::new()];
+
+    let mut start = 0;
+    while start < input.len() {
+      let separators = input[start..]
+        .iter()
+        .take_while(|x| **x == SEPARATOR)
+        .count();
+      let len = input[start + separators..]
+        .iter()
+        .take_while(|x| !self.is_boundary(**x))
+        .count();
+      let keys = &input[start + separators..start + separators + len];
+      start += separators + len;
+
+      if keys.is_empty() {
+        // a tone key follows no syllable
+        if start < input.len() {
+          return vec![];
+        }
+
+        // trailing separators belong to the last segment
+        for segmentation in res.iter_mut() {
+          match segmentation.last_mut() {
+            Some(last) => last.keys.extend(&input[start - separators..start]),
+            None => return vec![],
+          }
+        }
+        continue;
+      }
+
+      let mut parts: Vec<Segmentation> = match &self.scheme {
+        Some(scheme) => scheme.decode(keys, &self.table).into_iter().collect(),
+        None => self.segment_full(keys),
+      };
+      let tone = input.get(start).and_then(|x| self.tone(*x));
+      // a tone ends the syllable typed, e.g. `ma3` is never `m` + `a3`, so
+      // the keys before it are read as few syllables as possible
+      if tone.is_some() {
+        let fewest = parts.iter().map(|part| part.len()).min().unwrap_or(0);
+        parts.retain(|part| part.len() == fewest);
+      }
+      let mut next: Vec<Segmentation> = Vec::new();
+      for prefix in &res {
+        for part in &parts {
+          let mut segmentation = prefix.clone();
+          segmentation.extend(part.iter().cloned());
+          segmentation[prefix.len()].keys.splice(
+            0..0,
+            input[start - len - separators..start - len].iter().cloned(),
+          );
+          next.push(segmentation);
+        }
+      }
+      next.sort_by_key(|segmentation| segmentation.len());
+      next.truncate(MAX_SEGMENTATIONS);
+
+      if let Some(tone) = tone {
+        for segmentation in next.iter_mut() {
+          if let Some(last) = segmentation.last_mut() {
+            last.keys.push(input[start]);
+            last.tone = Some(tone);
+          }
+        }
+        start += 1;
+      }
+
+      res = next;
+    }
+    res.retain(|segmentation| !segmentation.is_empty());
+
+    for segmentation in res.iter_mut() {
+      for segment in segmentation.iter_mut() {
+        self.expand_fuzzy(segment);
+      }
+    }
+
+    res
+  }
+
+  fn expand_fuzzy(&self, segment: &mut Segment) {
+    let mut fuzzy: Vec<(Syllable, MatchType)> = Vec::new();
+
+    for (syllable, match_type) in &segment.syllables {
+      for x in self.fuzzy.expand(syllable, &self.table) {
+        let exists = segment.syllables.iter().any(|(s, _)| *s == x);
+        if !exists && !fuzzy.iter().any(|(s, _)| *s == x) {
+          fuzzy.push((x, max(*match_type, MatchType::FuzzyMatch)));
+        }
+      }
+    }
+
+    segment.syllables.extend(fuzzy);
+  }
+
+  fn segment_full(&self, keys: &[char]) -> Vec<Segmentation> {
+    if keys.is_empty() {
+      return vec![];
+    }
+
+    // reachable[i]: if `keys[i..]` can be segmented
+    let mut reachable = vec![false; keys.len() + 1];
+    reachable[keys.len()] = true;
+    for i in (0..keys.len()).rev() {
+      reachable[i] = self.is_tail(&keys[i..])
+        || (1..=self.table.max_length())
+          .filter(|len| i + len <= keys.len())
+          .any(|len| {
+            let window = keys_str(&keys[i..i + len]);
+            reachable[i + len]
+              && (self.table.parse(&window).is_some() || self.is_abbreviation(&window))
+          });
+    }
+
+    let mut res: Vec<Segmentation> = Vec::new();
+    if reachable[0] {
+      self.search(keys, 0, &reachable, &mut Vec::new(), &mut res);
+    }
+    res.sort_by_key(|segmentation| segmentation.len());
+
+    res
+  }
+
+  fn is_tail(&self, keys: &[char]) -> bool {
+    if keys.len() >= self.table.max_length() {
+      return false;
+    }
+
+    let keys = keys_str(keys);
+    self.table.is_prefix(&keys) && self.table.parse(&keys).is_none()
+  }
+
+  fn is_abbreviation(&self, keys: &str) -> bool {
+    PinyinInitials::parse(keys).is_some()
+  }
+
+  fn search(
+    &self,
+    keys: &[char],
+    start: usize,
+    reachable: &[bool],
+    current: &mut Segmentation,
+    res: &mut Vec<Segmentation>,
+  ) {
+    if res.len() >= MAX_SEGMENTATIONS {
+      return;
+    }
+    if start == keys.len() {
+      res.push(current.clone());
+      return;
+    }
+
+    for len in (1..=self.table.max_length()).rev() {
+      if start + len > keys.len() || !reachable[start + len] {
+        continue;
+      }
+
+      if let Some(syllable) = self.table.parse(&keys_str(&keys[start..start + len])) {
+        current.push(Segment {
+          keys: keys[start..start + len].to_vec(),
+          syllables: vec![(syllable, MatchType::PerfectMatch)],
+          tone: None,
+        });
+        self.search(keys, start + len, reachable, current, res);
+        current.pop();
+      }
+    }
+
+    // initials before the last segment, the last one is a tail below
+    for len in (1..=2).rev() {
+      if start + len >= keys.len() || !reachable[start + len] {
+        continue;
+      }
+
+      let initial = keys_str(&keys[start..start + len]);
+      if self.is_abbreviation(&initial) {
+        current.push(Segment {
+          keys: keys[start..start + len].to_vec(),
+          syllables: self
+            .table
+            .completions(&initial)
+            .into_iter()
+            .map(|syllable| (syllable, MatchType::AbbreviatedMatch))
+            .collect(),
+          tone: None,
+        });
+        self.search(keys, start + len, reachable, current, res);
+        current.pop();
+      }
+    }
+
+    if self.is_tail(&keys[start..]) {
+      current.push(Segment {
+        keys: keys[start..].to_vec(),
+        syllables: self
+          .table
+          .completions(&keys_str(&keys[start..]))
+          .into_iter()
+          .map(|syllable| (syllable, MatchType::PrefixMatch))
+          .collect(),
+        tone: None,
+      });
+      self.search(keys, keys.len(), reachable, current, res);
+      current.pop();
+    }
+  }
+}
+
+fn keys_str(keys: &[char]) -> String {
+  keys.iter().collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn segmenter(tone_keys: &str) -> Segmenter {
+    Segmenter::new(
+      None,
+      FuzzySyllables::new(vec![]).unwrap(),
+      tone_keys.chars().collect(),
+    )
+  }
+
+  fn spellings(segmentation: &Segmentation) -> Vec<String> {
+    segmentation.iter().map(|x| x.spelling()).collect()
+  }
+
+  fn segment(segmenter: &Segmenter, input: &str) -> Vec<Vec<String>> {
+    segmenter
+      .segment(&input.chars().collect::<Vec<char>>())
+      .iter()
+      .map(spellings)
+      .collect()
+  }
+
+  fn has(segment: &Segment, spelling: &str, match_type: MatchType) -> bool {
+    segment
+      .syllables
+      .iter()
+      .any(|(syllable, x)| syllable.spelling() == spelling && *x == match_type)
+  }
+
+  #[test]
+  fn fewer_syllables_first() {
+    let res = segment(&segmenter(""), "xian");
+
+    assert_eq!(res[0], vec!["xian"]);
+    assert!(res.contains(&vec!["xi".to_string(), "an".to_string()]));
+  }
+
+  #[test]
+  fn separators() {
+    let segmenter = segmenter("");
+
+    // no syllable spans a separator
+    let res = segment(&segmenter, "xi'an");
+    assert_eq!(res[0], vec!["xi", "an"]);
+    assert!(res.iter().all(|x| x[0] == "xi"));
+    assert_eq!(segment(&segmenter, "xi'an'")[0], vec!["xi", "an"]);
+
+    let res = segmenter.segment(&"xi'an".chars().collect::<Vec<char>>());
+    assert_eq!(res[0][1].keys, vec!['\'', 'a', 'n']);
+  }
+
+  #[test]
+  fn abbreviation() {
+    let res = segmenter("").segment(&"zhg".chars().collect::<Vec<char>>());
+    let segmentation = res
+      .iter()
+      .find(|x| spellings(x) == vec!["zh", "g"])
+      .unwrap();
+
+    assert!(has(&segmentation[0], "zhong", MatchType::AbbreviatedMatch));
+    assert!(has(&segmentation[1], "guo", MatchType::PrefixMatch));
+  }
+
+  #[test]
+  fn incomplete_last_syllable() {
+    let res = segmenter("").segment(&"zhongg".chars().collect::<Vec<char>>());
+
+    assert_eq!(spellings(&res[0]), vec!["zhong", "g"]);
+    assert!(has(&res[0][0], "zhong", MatchType::PerfectMatch));
+    assert!(has(&res[0][1], "guo", MatchType::PrefixMatch));
+    assert!(segment(&segmenter(""), "zhongv").is_empty());
+  }
+
+  #[test]
+  fn fuzzy_expansion() {
+    let segmenter = Segmenter::new(
+      None,
+      FuzzySyllables::new(vec![("z".to_string(), "zh".to_string())]).unwrap(),
+      vec![],
+    );
+    let res = segmenter.segment(&"zong".chars().collect::<Vec<char>>());
+
+    assert!(has(&res[0][0], "zong", MatchType::PerfectMatch));
+    assert!(has(&res[0][0], "zhong", MatchType::FuzzyMatch));
+  }
+
+  #[test]
+  fn keys_out_of_syllables() {
+    let segmenter = segmenter("");
+
+    assert!(segment(&segmenter, "zhōng").is_empty());
+    assert!(segment(&segmenter, "中").is_empty());
+    assert_eq!(segment(&segmenter, "zhong")[0], vec!["zhong"]);
+  }
+
+  #[test]
+  fn tone_ends_the_syllable_typed() {
+    let res = segmenter("12345").segment(&"ma3".chars().collect::<Vec<char>>());
+
+    assert_eq!(res.len(), 1);
+    assert_eq!(spellings(&res[0]), vec!["ma3"]);
+    assert_eq!(res[0][0].tone, Some(3));
+  }
+
+  #[test]
+  fn tone_after_several_syllables() {
+    let res = segmenter("12345").segment(&"zhongguo2".chars().collect::<Vec<char>>());
+
+    assert!(!res.is_empty());
+    for segmentation in &res {
+      assert_eq!(spellings(segmentation), vec!["zhong", "guo2"]);
+      assert_eq!(segmentation[0].tone, None);
+    }
+  }
+
+  #[test]
+  fn tone_key_without_syllable() {
+    let segmenter = segmenter("12345");
+
+    assert!(segmenter.segment(&['3', 'm', 'a']).is_empty());
+    assert!(segmenter.segment(&['m', 'a', '\'', '3']).is_empty());
+  }
+}
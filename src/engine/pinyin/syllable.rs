@@ -0,0 +1,230 @@
+use super::codes::{PinyinCode, PinyinFinals, PinyinInitials};
+use std::collections::HashSet;
+
+static SYLLABLES: &str = "
+  a ai an ang ao e ei en eng er o ou
+  ba bai ban bang bao bei ben beng bi bian biao bie bin bing bo bu
+  pa pai pan pang pao pei pen peng pi pian piao pie pin ping po pou pu
+  ma mai man mang mao me mei men meng mi mian miao mie min ming miu mo mou mu
+  fa fan fang fei fen feng fo fou fu
+  da dai dan dang dao de dei den deng di dia dian diao die ding diu dong dou du duan dui dun duo
+  ta tai tan tang tao te tei teng ti tian tiao tie ting tong tou tu tuan tui tun tuo
+  na nai nan nang nao ne nei nen neng ni nian niang niao nie nin ning niu nong nou nu nuan nun nuo nv nve
+  la lai lan lang lao le lei leng li lia lian liang liao lie lin ling liu lo long lou lu luan lun luo lv lve
+  ga gai gan gang gao ge gei gen geng gong gou gu gua guai guan guang gui gun guo
+  ka kai kan kang kao ke kei ken keng kong kou ku kua kuai kuan kuang kui kun kuo
+  ha hai han hang hao he hei hen heng hong hou hu hua huai huan huang hui hun huo
+  ji jia jian jiang jiao jie jin jing jiong jiu ju juan jue jun
+  qi qia qian qiang qiao qie qin qing qiong qiu qu quan que qun
+  xi xia xian xiang xiao xie xin xing xiong xiu xu xuan xue xun
+  zha zhai zhan zhang zhao zhe zhei zhen zheng zhi zhong zhou zhu zhua zhuai zhuan zhuang zhui zhun zhuo
+  cha chai chan chang chao che chen cheng chi chong chou chu chua chuai chuan chuang chui chun chuo
+  sha shai shan shang shao she shei shen sheng shi shou shu shua shuai shuan shuang shui shun shuo
+  ran rang rao re ren reng ri rong rou ru rua ruan rui run ruo
+  za zai zan zang zao ze zei zen zeng zi zong zou zu zuan zui zun zuo
+  ca cai can cang cao ce cen ceng ci cong cou cu cuan cui cun cuo
+  sa sai san sang sao se sen seng si song sou su suan sui sun suo
+  ya yan yang yao ye yi yin ying yo yong you yu yuan yue yun
+  wa wai wan wang wei wen weng wo wu
+";
+
+// vowels and their tone marks of tone 1 to 4
+static TONE_MARKS: &[(char, &str)] = &[
+  ('a', "āáǎà"),
+  ('e', "ēéěè"),
+  ('i', "īíǐì"),
+  ('o', "ōóǒò"),
+  ('u', "ūúǔù"),
+  ('v', "ǖǘǚǜ"),
+];
+
+/// A complete pinyin syllable, an optional initial followed by a final.
+///
+/// Syllables are kept in their written form, so `yu` is `Y` + `U` and `lv` is
+/// `L` + `V`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Syllable {
+  pub initial: Option<PinyinInitials>,
+  pub finals: PinyinFinals,
+}
+
+impl Syllable {
+  fn split(s: &str) -> Option<Syllable> {
+    for len in (1..=2).rev() {
+      if s.len() > len {
+        if let Some(initial) = PinyinInitials::parse(&s[..len]) {
+          if let Some(finals) = PinyinFinals::parse(&s[len..]) {
+            return Some(Syllable {
+              initial: Some(initial),
+              finals,
+            });
+          }
+        }
+      }
+    }
+
+    Some(Syllable {
+      initial: None,
+      finals: PinyinFinals::parse(s)?,
+    })
+  }
+
+  pub fn codes(&self) -> Vec<PinyinCode> {
+    let mut res = Vec::new();
+
+    if let Some(initial) = self.initial {
+      res.push(PinyinCode::Initials(initial));
+    }
+    res.push(PinyinCode::Finals(self.finals));
+
+    res
+  }
+
+  pub fn spelling(&self) -> String {
+    let mut res = String::new();
+
+    if let Some(initial) = self.initial {
+      res.push_str(initial.as_str());
+    }
+    res.push_str(self.finals.as_str());
+
+    res
+  }
+}
+
+/// All valid syllables, used to validate and segment raw key sequences.
+pub struct SyllableTable {
+  syllables: HashSet<String>,
+  prefixes: HashSet<String>,
+  max_length: usize,
+}
+
+impl SyllableTable {
+  pub fn new() -> SyllableTable {
+    let mut table = SyllableTable {
+      syllables: HashSet::new(),
+      prefixes: HashSet::new(),
+      max_length: 0,
+    };
+
+    for syllable in SYLLABLES.split_whitespace() {
+      for i in 1..syllable.len() {
+        table.prefixes.insert(syllable[..i].to_string());
+      }
+      table.max_length = table.max_length.max(syllable.len());
+      table.syllables.insert(syllable.to_string());
+    }
+
+    table
+  }
+
+  pub fn parse(&self, s: &str) -> Option<Syllable> {
+    if self.syllables.contains(s) {
+      Syllable::split(s)
+    } else {
+      None
+    }
+  }
+
+  /// Parses a syllable written without tone, with a tone number (`zhong1`,
+  /// 5 for the neutral tone) or with a tone mark (`zhōng`), `ü` written as
+  /// `ü` or `v`. Returns the syllable and its tone, if written.
+  pub fn parse_toned(&self, s: &str) -> Option<(Syllable, Option<u8>)> {
+    let mut plain = String::new();
+    let mut tone = None;
+
+    for ch in s.to_lowercase().chars() {
+      let marked = TONE_MARKS.iter().find_map(|(vowel, marks)| {
+        let i = marks.chars().position(|x| x == ch)?;
+        Some((*vowel, i as u8 + 1))
+      });
+
+      match marked {
+        // a syllable has one tone
+        Some(_) if tone.is_some() => return None,
+        Some((vowel, mark_tone)) => {
+          plain.push(vowel);
+          tone = Some(mark_tone);
+        }
+        None if ch == 'ü' => plain.push('v'),
+        None => plain.push(ch),
+      }
+    }
+
+    if let Some(digit) = plain.chars().last().and_then(|x| x.to_digit(10)) {
+      if tone.is_some() || digit < 1 || digit > 5 {
+        return None;
+      }
+      plain.pop();
+      tone = Some(digit as u8);
+    }
+
+    Some((self.parse(&plain)?, tone))
+  }
+
+  pub fn syllable(
+    &self,
+    initial: Option<PinyinInitials>,
+    finals: PinyinFinals,
+  ) -> Option<Syllable> {
+    self.parse(&Syllable { initial, finals }.spelling())
+  }
+
+  pub fn is_prefix(&self, s: &str) -> bool {
+    self.prefixes.contains(s)
+  }
+
+  /// Complete syllables starting with `prefix`, `prefix` itself excluded.
+  pub fn completions(&self, prefix: &str) -> Vec<Syllable> {
+    let mut res: Vec<Syllable> = self
+      .syllables
+      .iter()
+      .filter(|s| s.len() > prefix.len() && s.starts_with(prefix))
+      .filter_map(|s| Syllable::split(s))
+      .collect();
+    res.sort_by_key(|s| s.spelling());
+
+    res
+  }
+
+  pub fn max_length(&self) -> usize {
+    self.max_length
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parse_toned() {
+    let table = SyllableTable::new();
+    let zhong = table.parse("zhong");
+
+    assert_eq!(table.parse_toned("zhong").map(|x| x.1), Some(None));
+    assert_eq!(table.parse_toned("zhong1").map(|x| x.1), Some(Some(1)));
+    assert_eq!(table.parse_toned("zhōng").map(|x| x.0), zhong);
+    assert_eq!(table.parse_toned("zhōng").map(|x| x.1), Some(Some(1)));
+    assert_eq!(table.parse_toned("ma5").map(|x| x.1), Some(Some(5)));
+    assert_eq!(table.parse_toned("lǜ").map(|x| x.0), table.parse("lv"));
+    assert_eq!(table.parse_toned("lü4").map(|x| x.1), Some(Some(4)));
+
+    assert!(table.parse_toned("zhong6").is_none());
+    assert!(table.parse_toned("zhōng1").is_none());
+    assert!(table.parse_toned("zhongx").is_none());
+  }
+
+  #[test]
+  fn prefixes_and_completions() {
+    let table = SyllableTable::new();
+
+    assert!(table.is_prefix("zh"));
+    assert!(!table.is_prefix("zhong"));
+    let completions: Vec<String> = table
+      .completions("xio")
+      .iter()
+      .map(|x| x.spelling())
+      .collect();
+    assert_eq!(completions, vec!["xiong"]);
+  }
+}
@@ -1,5 +1,3 @@
-#![feature(get_mut_unchecked)]
-
 pub mod data;
 pub mod engine;
 pub mod output;
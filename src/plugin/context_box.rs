@@ -4,7 +4,7 @@ use async_std::io::Stdout;
 use log::info;
 use nvim_rs::{neovim_api, neovim_api_manual, rpc::unpack::TryUnpack, Buffer, Neovim, Window};
 use rmpv::Value;
-use std::cmp::{max, min};
+use std::cmp::max;
 
 pub static CANDIDATE_PER_PAGE: usize = 7;
 
@@ -46,9 +46,14 @@ pub struct ContextWindow {
 }
 
 pub struct ContextBox {
+  // candidates on the current page only
   candidates: Vec<Candidate>,
   page: usize,
+  // if there are pages after the current one
+  has_more: bool,
   codes: Vec<String>,
+  // position of the cursor in the codes joined by `'`
+  cursor: usize,
   win_info: Option<ContextWindow>,
 }
 
@@ -68,11 +73,18 @@ macro_rules! eval_vim {
 }
 
 impl ContextBox {
-  pub fn new(codes: Vec<String>, candidates: Vec<Candidate>) -> ContextBox {
+  pub fn new(
+    codes: Vec<String>,
+    cursor: usize,
+    candidates: Vec<Candidate>,
+    has_more: bool,
+  ) -> ContextBox {
     ContextBox {
       codes,
+      cursor,
       candidates,
       page: 0,
+      has_more,
       win_info: None,
     }
   }
@@ -94,56 +106,52 @@ impl ContextBox {
     Ok(())
   }
 
-  fn max_page_id(&self) -> usize {
-    let mut res = self.candidates.len() / CANDIDATE_PER_PAGE;
-
-    if self.candidates.len() % CANDIDATE_PER_PAGE > 0 {
-      res += 1;
-    }
-
-    res
+  pub fn is_empty(&self) -> bool {
+    self.candidates.is_empty()
   }
 
-  fn candidate_slice(&self) -> &[Candidate] {
-    let st = self.page * CANDIDATE_PER_PAGE;
-    let ed = min((self.page + 1) * CANDIDATE_PER_PAGE, self.candidates.len());
-
-    &self.candidates[st..ed]
+  pub fn page(&self) -> usize {
+    self.page
   }
 
-  // @return changed
-  pub fn previous_page(&mut self) -> bool {
-    if self.page == 0 {
-      false
-    } else {
-      self.page -= 1;
+  pub fn has_more(&self) -> bool {
+    self.has_more
+  }
 
-      true
-    }
+  /// Shows `page`, whose candidates are fetched from the input context.
+  pub fn set_page(&mut self, page: usize, candidates: Vec<Candidate>, has_more: bool) {
+    self.page = page;
+    self.candidates = candidates;
+    self.has_more = has_more;
   }
 
-  // @return changed
-  pub fn next_page(&mut self) -> bool {
-    if self.page < self.max_page_id() {
-      self.page += 1;
+  /// Raw codes of the input, confirmed when there's no candidate.
+  pub fn raw_codes(&self) -> String {
+    self.codes.join("")
+  }
 
-      true
+  /// Index among all candidates of the input of the `idx`th candidate shown,
+  /// starting from 1.
+  pub fn index(&self, idx: i64) -> Option<usize> {
+    if idx < 1 || idx as usize > self.candidates.len() {
+      None
     } else {
-      false
+      Some(self.page * CANDIDATE_PER_PAGE + idx as usize - 1)
     }
   }
 
-  pub fn confirm(&self, idx: i64) -> Option<String> {
-    let candidates = self.candidate_slice();
-    if candidates.len() == 0 {
-      Some(self.codes.join(""))
-    } else {
-      if candidates.len() >= idx as usize {
-        Some(candidates[idx as usize - 1].text.clone())
-      } else {
-        None
-      }
+  // codes of the input with a bar at the cursor, which is hidden at the end
+  fn preedit(&self) -> String {
+    let text = self.codes.join("'");
+    let len = text.chars().count();
+    if self.cursor >= len {
+      return text;
     }
+
+    let mut res: String = text.chars().take(self.cursor).collect();
+    res.push('|');
+    res.extend(text.chars().skip(self.cursor));
+    res
   }
 
   async fn create_floating_window(&mut self, neovim: &Neovim<Stdout>) -> Result<(), Value> {
@@ -184,22 +192,27 @@ impl ContextBox {
 
   async fn render_select_box(&self, _neovim: &Neovim<Stdout>) -> Result<(), Value> {
     if let Some(info) = &self.win_info {
-      let candidates = self.candidate_slice();
+      let candidates = &self.candidates;
       info!("candidates this page: {:?}", candidates);
 
       let lines: Vec<String> = vec![
-        self.codes.join("'"),
+        self.preedit(),
         "--".to_string(),
         candidates
           .iter()
           .enumerate()
           .map(|(i, candidate)| {
-            format!(
+            let mut res = format!(
               "{}.{}{}",
               i + 1,
               candidate.text,
               candidate.remain_codes.iter().collect::<String>()
-            )
+            );
+            // teach the whole code
+            if !candidate.codes.is_empty() {
+              res.push_str(&format!("({})", candidate.codes.iter().collect::<String>()));
+            }
+            res
           })
           .collect::<Vec<String>>()
           .join("  "),
@@ -1,5 +1,7 @@
 use super::context_box::{ContextBox, CANDIDATE_PER_PAGE};
-use crate::engine::{BackspaceResult, Candidate, IMEngine, InputContext};
+use crate::engine::{
+  BackspaceResult, Candidate, ConfirmResult, CursorMotion, Engines, IMEngine, InputContext,
+};
 use async_std;
 use async_std::io::Stdout;
 use async_std::sync::Mutex;
@@ -13,10 +15,15 @@ use uuid::Uuid;
 
 #[derive(Clone)]
 pub struct PluginManager {
-  engine: Arc<Mutex<dyn IMEngine>>,
+  engines: Arc<Engines>,
+  default_engine: String,
+  // engine whose codes are shown next to candidates of each engine
+  reverse_lookup: Arc<HashMap<String, String>>,
+  // engine selected by each buffer
+  buffer_engine: Arc<Mutex<HashMap<i64, String>>>,
   contexts: Arc<Mutex<HashMap<String, Arc<Mutex<dyn InputContext>>>>>,
   buffer_box: Arc<Mutex<HashMap<i64, Arc<Mutex<ContextBox>>>>>,
-  mappings: Arc<Mutex<HashSet<String>>>,
+  mappings: Arc<Mutex<HashMap<i64, HashSet<String>>>>,
 }
 
 #[macro_export]
@@ -60,40 +67,84 @@ impl NeovimHandler for PluginManager {
     neovim: Neovim<Self::Writer>,
   ) -> Result<Value, Value> {
     match name.as_ref() {
-      "register_events" => self.register_events(neovim).await,
-      "unregister_events" => self.unregister_events(neovim).await,
+      "register_events" => self.register_events(args, neovim).await,
+      "unregister_events" => self.unregister_events(args, neovim).await,
+      "switch_engine" => self.switch_engine(args, neovim).await,
+      "list_engines" => self.list_engines(args, neovim).await,
+      "add_phrase" => self.add_phrase(args, neovim).await,
+      "delete_phrase" => self.delete_phrase(args, neovim).await,
+      "reverse_lookup" => self.reverse_lookup(args, neovim).await,
       "start_context" => self.start_context(args, neovim).await,
       "input_char" => self.input_char(args, neovim).await,
+      "accepts_tone" => self.accepts_tone(args, neovim).await,
       "next_page" => self.next_page(args, neovim).await,
       "previous_page" => self.previous_page(args, neovim).await,
       "backspace" => self.backspace(args, neovim).await,
+      "delete" => self.delete(args, neovim).await,
+      "move_cursor" => self.move_cursor(args, neovim).await,
       "cancel" => self.cancel(args, neovim).await,
       "confirm" => self.confirm(args, neovim).await,
+      "flush" => self.flush(args, neovim).await,
       _ => Err(Value::from(format!("no method named: '{}'", name))),
     }
   }
 }
 
 impl PluginManager {
-  pub fn new(engine: Arc<Mutex<dyn IMEngine>>) -> PluginManager {
+  pub fn new(
+    default_engine: String,
+    engines: Engines,
+    reverse_lookup: HashMap<String, String>,
+  ) -> PluginManager {
     PluginManager {
-      engine: engine,
+      engines: Arc::new(engines),
+      default_engine,
+      reverse_lookup: Arc::new(reverse_lookup),
+      buffer_engine: Arc::new(Mutex::new(HashMap::new())),
       contexts: Arc::new(Mutex::new(HashMap::new())),
       buffer_box: Arc::new(Mutex::new(HashMap::new())),
-      mappings: Arc::new(Mutex::new(HashSet::new())),
+      mappings: Arc::new(Mutex::new(HashMap::new())),
     }
   }
 
-  async fn start_context(
-    &self,
-    _args: Vec<Value>,
-    _neovim: Neovim<Stdout>,
-  ) -> Result<Value, Value> {
+  /// Writes what every engine learned and hasn't saved yet.
+  pub async fn flush_engines(&self) {
+    for (name, engine) in self.engines.iter() {
+      info!("flush engine {}", name);
+      engine.lock().await.flush();
+    }
+  }
+
+  async fn engine_name(&self, bufnr: i64) -> String {
+    match self.buffer_engine.lock().await.get(&bufnr) {
+      Some(name) => name.clone(),
+      None => self.default_engine.clone(),
+    }
+  }
+
+  async fn engine(&self, bufnr: i64) -> Result<Arc<Mutex<dyn IMEngine>>, Value> {
+    let name = self.engine_name(bufnr).await;
+
+    self
+      .engines
+      .get(&name)
+      .cloned()
+      .ok_or_else(|| Value::from(format!("engine '{}' is not loaded", name)))
+  }
+
+  async fn start_context(&self, args: Vec<Value>, _neovim: Neovim<Stdout>) -> Result<Value, Value> {
+    if args.len() < 1 {
+      return Err(Value::from("expect at least 1 argument"));
+    }
+    let bufnr = args[0]
+      .as_i64()
+      .ok_or_else(|| Value::from("first parameter should be int"))?;
+
     let uuid = Uuid::new_v4().to_hyphenated().to_string();
 
     info!("'start_context': generated uuid: {}", uuid);
 
-    let context = self.engine.lock().await.start_context_async();
+    let context = self.engine(bufnr).await?.lock().await.start_context_async();
     self.contexts.lock().await.insert(uuid.clone(), context);
 
     Ok(Value::from(uuid))
@@ -101,24 +152,41 @@ impl PluginManager {
 
   async fn input_char(&self, _args: Vec<Value>, _neovim: Neovim<Stdout>) -> Result<Value, Value> {
     // args: [context_id, char, bufnr]
-    let ((candidates, codes), bufnr) = self._input_char_impl(_args).await?;
+    let ((candidates, codes), committed, bufnr) = self._input_char_impl(_args.clone()).await?;
 
     // info!(
     //   "construct ctx_box with candidates: {:?}, codes: {:?}, bufnr: {}",
     //   candidates, codes, bufnr
     // );
 
-    self
-      .render_new_buffer_box(bufnr, candidates, codes, &_neovim)
-      .await?;
+    if let Some(txt) = &committed {
+      self.engine(bufnr).await?.lock().await.commit(txt);
+      info!("context committed txt: {}", txt);
+    }
+
+    // the context committed or cleared everything it had, composition is over
+    let done = candidates.is_empty() && codes.concat().is_empty();
+    if done {
+      self
+        .cancel(make_args![_args[0].clone(), bufnr], _neovim)
+        .await?;
+    } else {
+      let ctx_id = _args[0].as_str().unwrap_or_default();
+      self
+        .render_new_buffer_box(bufnr, ctx_id, codes, &_neovim)
+        .await?;
+    }
 
-    Ok(Value::from(""))
+    Ok(Value::from(vim_dict! {
+      "text" => committed.unwrap_or_default(),
+      "done" => done,
+    }))
   }
 
   async fn _input_char_impl(
     &self,
     args: Vec<Value>,
-  ) -> Result<((Vec<Candidate>, Vec<String>), i64), Value> {
+  ) -> Result<((Vec<Candidate>, Vec<String>), Option<String>, i64), Value> {
     if args.len() < 3 {
       Err(Value::from("expect args but not found"))
     } else {
@@ -144,28 +212,96 @@ impl PluginManager {
         .ok_or_else(|| Value::from("context not exists"))?
         .clone();
 
-      Ok((
-        ctx.clone().lock().await.feed(ch.chars().next().unwrap()),
-        bufnr,
-      ))
+      let mut ctx = ctx.lock().await;
+      let res = ctx.feed(ch.chars().next().unwrap());
+
+      Ok((res, ctx.take_commit(), bufnr))
     }
   }
 
+  async fn accepts_tone(&self, args: Vec<Value>, _neovim: Neovim<Stdout>) -> Result<Value, Value> {
+    if args.len() < 2 {
+      return Err(Value::from("expect at least 2 arguments"));
+    }
+
+    let ctx_id = args[0]
+      .as_str()
+      .ok_or_else(|| Value::from("first parameter should be str"))?;
+    let key = args[1]
+      .as_str()
+      .and_then(|x| x.chars().next())
+      .ok_or_else(|| Value::from("second parameter should be char"))?;
+
+    let ctx = self
+      .contexts
+      .lock()
+      .await
+      .get(ctx_id)
+      .ok_or_else(|| Value::from("context not exists"))?
+      .clone();
+    let res = ctx.lock().await.accepts_tone(key);
+
+    Ok(Value::from(res))
+  }
+
   async fn next_page(&self, args: Vec<Value>, neovim: Neovim<Stdout>) -> Result<Value, Value> {
-    if args.len() < 1 {
-      return Err(Value::from("expect at least 1 argument"));
+    self.turn_page(args, neovim, true).await
+  }
+
+  async fn previous_page(&self, args: Vec<Value>, neovim: Neovim<Stdout>) -> Result<Value, Value> {
+    self.turn_page(args, neovim, false).await
+  }
+
+  async fn turn_page(
+    &self,
+    args: Vec<Value>,
+    neovim: Neovim<Stdout>,
+    forward: bool,
+  ) -> Result<Value, Value> {
+    // args: [context_id, bufnr]
+    if args.len() < 2 {
+      return Err(Value::from("expect at least 2 arguments"));
     }
-    let bufnr = args[0]
+    let ctx_id = args[0]
+      .as_str()
+      .ok_or_else(|| Value::from("first parameter should be str"))?;
+    let bufnr = args[1]
       .as_i64()
-      .ok_or_else(|| Value::from("third parameter should be int"))?;
+      .ok_or_else(|| Value::from("second parameter should be int"))?;
+
+    let ctx = self
+      .contexts
+      .lock()
+      .await
+      .get(ctx_id)
+      .ok_or_else(|| Value::from("context not exists"))?
+      .clone();
+
     match self.buffer_box.lock().await.get(&bufnr) {
       Some(_buf_box) => {
         info!("buf box found!");
 
         let mut buf_box = _buf_box.lock().await;
-        if buf_box.next_page() {
-          buf_box.render(&neovim).await?;
-        }
+        let page = buf_box.page();
+        let page = if forward && buf_box.has_more() {
+          page + 1
+        } else if !forward && page > 0 {
+          page - 1
+        } else {
+          return Ok(Value::from("ok"));
+        };
+
+        // only the candidates shown are fetched from the context
+        let (mut candidates, has_more) = {
+          let mut ctx = ctx.lock().await;
+          (
+            ctx.candidates(page, CANDIDATE_PER_PAGE),
+            ctx.has_more(page, CANDIDATE_PER_PAGE),
+          )
+        };
+        self.annotate(bufnr, &mut candidates).await;
+        buf_box.set_page(page, candidates, has_more);
+        buf_box.render(&neovim).await?;
       }
       None => {}
     }
@@ -173,29 +309,38 @@ impl PluginManager {
     Ok(Value::from("ok"))
   }
 
-  async fn previous_page(&self, args: Vec<Value>, neovim: Neovim<Stdout>) -> Result<Value, Value> {
-    if args.len() < 1 {
-      return Err(Value::from("expect at least 1 argument"));
+  async fn backspace(&self, args: Vec<Value>, neovim: Neovim<Stdout>) -> Result<Value, Value> {
+    if args.len() < 2 {
+      return Err(Value::from("expect at least 2 arguments."));
     }
-    let bufnr = args[0]
+
+    let ctx_id = args[0]
+      .as_str()
+      .ok_or_else(|| Value::from("first parameter should be str"))?;
+    let bufnr = args[1]
       .as_i64()
-      .ok_or_else(|| Value::from("third parameter should be int"))?;
-    match self.buffer_box.lock().await.get(&bufnr) {
-      Some(_buf_box) => {
-        info!("buf box found!");
+      .ok_or_else(|| Value::from("second parameter should be int"))?;
 
-        let mut buf_box = _buf_box.lock().await;
-        if buf_box.previous_page() {
-          buf_box.render(&neovim).await?;
-        }
+    let ctx = self
+      .contexts
+      .lock()
+      .await
+      .get_mut(ctx_id)
+      .ok_or_else(|| Value::from("context not exists"))?
+      .clone();
+
+    let res = ctx.lock().await.backspace();
+    match res {
+      BackspaceResult::Candidates(_, codes) => {
+        self
+          .render_new_buffer_box(bufnr, ctx_id, codes, &neovim)
+          .await
       }
-      None => {}
+      BackspaceResult::Cancel => self.cancel(args, neovim).await,
     }
-
-    Ok(Value::from("ok"))
   }
 
-  async fn backspace(&self, args: Vec<Value>, neovim: Neovim<Stdout>) -> Result<Value, Value> {
+  async fn delete(&self, args: Vec<Value>, neovim: Neovim<Stdout>) -> Result<Value, Value> {
     if args.len() < 2 {
       return Err(Value::from("expect at least 2 arguments."));
     }
@@ -215,16 +360,58 @@ impl PluginManager {
       .ok_or_else(|| Value::from("context not exists"))?
       .clone();
 
-    match ctx.clone().lock().await.backspace() {
-      BackspaceResult::Candidates(candidates, codes) => {
+    let res = ctx.lock().await.delete();
+    match res {
+      BackspaceResult::Candidates(_, codes) => {
         self
-          .render_new_buffer_box(bufnr, candidates, codes, &neovim)
+          .render_new_buffer_box(bufnr, ctx_id, codes, &neovim)
           .await
       }
       BackspaceResult::Cancel => self.cancel(args, neovim).await,
     }
   }
 
+  async fn move_cursor(&self, args: Vec<Value>, neovim: Neovim<Stdout>) -> Result<Value, Value> {
+    if args.len() < 3 {
+      return Err(Value::from("expect at least 3 arguments."));
+    }
+
+    let ctx_id = args[0]
+      .as_str()
+      .ok_or_else(|| Value::from("first parameter should be str"))?;
+    let motion = match args[1].as_str() {
+      Some("left") => CursorMotion::Left,
+      Some("right") => CursorMotion::Right,
+      Some("start") => CursorMotion::Start,
+      Some("end") => CursorMotion::End,
+      _ => {
+        return Err(Value::from(
+          "second parameter should be one of 'left', 'right', 'start' and 'end'",
+        ))
+      }
+    };
+    let bufnr = args[2]
+      .as_i64()
+      .ok_or_else(|| Value::from("third parameter should be int"))?;
+
+    let ctx = self
+      .contexts
+      .lock()
+      .await
+      .get_mut(ctx_id)
+      .ok_or_else(|| Value::from("context not exists"))?
+      .clone();
+
+    let codes = {
+      let mut ctx = ctx.lock().await;
+      ctx.move_cursor(motion);
+      ctx.codes()
+    };
+    self
+      .render_new_buffer_box(bufnr, ctx_id, codes, &neovim)
+      .await
+  }
+
   async fn cancel(&self, args: Vec<Value>, neovim: Neovim<Stdout>) -> Result<Value, Value> {
     if args.len() < 2 {
       return Err(Value::from("expect at least 2 arguments."));
@@ -260,44 +447,103 @@ impl PluginManager {
       .as_i64()
       .ok_or_else(|| Value::from("third parameter should be int"))?;
 
-    let mut confirm_text: Option<String> = None;
-    {
-      match self.buffer_box.lock().await.get(&bufnr) {
-        Some(_buf_box) => {
-          info!("buf box found!");
-
-          let buf_box = _buf_box.lock().await;
-          if let Some(txt) = buf_box.confirm(idx) {
-            // if ok, cancel it
-            confirm_text = Some(txt.clone());
-          // Ok(Value::from(txt))
-          } else {
-            return Err(Value::from("index out of range"));
-          }
-        }
-        None => return Err(Value::from("no buffer box")),
+    let buf_box = self
+      .buffer_box
+      .lock()
+      .await
+      .get(&bufnr)
+      .cloned()
+      .ok_or_else(|| Value::from("no buffer box"))?;
+    let index = {
+      let buf_box = buf_box.lock().await;
+      if buf_box.is_empty() {
+        None
+      } else {
+        Some(
+          buf_box
+            .index(idx)
+            .ok_or_else(|| Value::from("index out of range"))?,
+        )
       }
-    }
-
-    if let Some(txt) = confirm_text {
-      self.cancel(make_args![ctx_id, bufnr], neovim).await;
+    };
+
+    let index = match index {
+      Some(index) => index,
+      None => {
+        // raw codes confirmed without candidates are not learned
+        let raw_codes = buf_box.lock().await.raw_codes();
+        self.cancel(make_args![ctx_id, bufnr], neovim).await?;
+        info!("confirm raw codes: {}", raw_codes);
+
+        return Ok(Value::from(vim_dict! {
+          "text" => raw_codes,
+          "done" => true,
+        }));
+      }
+    };
 
-      info!("confirm txt: {}", txt);
+    let ctx = self
+      .contexts
+      .lock()
+      .await
+      .get(ctx_id)
+      .ok_or_else(|| Value::from("context not exists"))?
+      .clone();
+    let res = ctx
+      .lock()
+      .await
+      .confirm(index)
+      .ok_or_else(|| Value::from("index out of range"))?;
 
-      Ok(Value::from(txt))
-    } else {
-      Err(Value::from("failed to confirm"))
-    }
+    let (txt, done) = match res {
+      ConfirmResult::Done(txt) => {
+        self.cancel(make_args![ctx_id, bufnr], neovim).await?;
+        (txt, true)
+      }
+      // the rest of the input stays for the next selection
+      ConfirmResult::Partial(txt, _, codes) => {
+        self
+          .render_new_buffer_box(bufnr, ctx_id, codes, &neovim)
+          .await?;
+        (txt, false)
+      }
+    };
+    self.engine(bufnr).await?.lock().await.commit(&txt);
+    info!("confirm txt: {}, done: {}", txt, done);
+
+    Ok(Value::from(vim_dict! {
+      "text" => txt,
+      "done" => done,
+    }))
   }
 
   async fn render_new_buffer_box(
     &self,
     bufnr: i64,
-    candidates: Vec<Candidate>,
+    ctx_id: &str,
     codes: Vec<String>,
     neovim: &Neovim<Stdout>,
   ) -> Result<Value, Value> {
-    let ctx_box = Arc::new(Mutex::new(ContextBox::new(codes, candidates)));
+    let ctx = self
+      .contexts
+      .lock()
+      .await
+      .get(ctx_id)
+      .ok_or_else(|| Value::from("context not exists"))?
+      .clone();
+    let (mut candidates, has_more, cursor) = {
+      let mut ctx = ctx.lock().await;
+      (
+        ctx.candidates(0, CANDIDATE_PER_PAGE),
+        ctx.has_more(0, CANDIDATE_PER_PAGE),
+        ctx.cursor(),
+      )
+    };
+    self.annotate(bufnr, &mut candidates).await;
+
+    let ctx_box = Arc::new(Mutex::new(ContextBox::new(
+      codes, cursor, candidates, has_more,
+    )));
     match self.buffer_box.lock().await.get(&bufnr) {
       Some(old) => {
         info!("old buffer box found. close it!");
@@ -312,15 +558,31 @@ impl PluginManager {
     Ok(Value::from("ok"))
   }
 
-  async fn register_events(&self, neovim: Neovim<Stdout>) -> Result<Value, Value> {
-    let keycodes = self.engine.lock().await.keycodes();
+  async fn register_events(
+    &self,
+    args: Vec<Value>,
+    neovim: Neovim<Stdout>,
+  ) -> Result<Value, Value> {
+    if args.len() < 1 {
+      return Err(Value::from("expect at least 1 argument"));
+    }
+    let bufnr = args[0]
+      .as_i64()
+      .ok_or_else(|| Value::from("first parameter should be int"))?;
+
+    let (keycodes, tone_keys, separators) = {
+      let engine = self.engine(bufnr).await?;
+      let engine = engine.lock().await;
+      (engine.keycodes(), engine.tone_keys(), engine.separators())
+    };
 
     let buf = neovim
       .get_current_buf()
       .await
       .map_err(|_| Value::from("failed to get current buffer"))?;
 
-    let mut mappings = self.mappings.lock().await;
+    let mut all_mappings = self.mappings.lock().await;
+    let mappings = all_mappings.entry(bufnr).or_default();
 
     macro_rules! inoremap {
       ($lhs:expr, $rhs:expr) => {
@@ -332,37 +594,269 @@ impl PluginManager {
     // mappings.insert("a".to_string());
 
     for ch in keycodes {
-      inoremap!(ch, format!("<C-R>=ime#rpc#input_char('{}')<C-M>", ch));
+      // separators are typed as they are where no input is going on
+      let function = if separators.contains(&ch) {
+        "feed_separator"
+      } else {
+        "input_char"
+      };
+      // quotes are doubled in single quoted strings of vim
+      inoremap!(
+        ch,
+        format!(
+          "<C-R>=ime#rpc#{}('{}')<C-M>",
+          function,
+          ch.to_string().replace('\'', "''")
+        )
+      );
     }
     inoremap!("<Space>", format!("<C-R>=ime#rpc#feed_space()<C-M>"));
     inoremap!("<Esc>", format!("<C-o>:call ime#rpc#cancel()<CR>"));
     inoremap!("<BS>", format!("<C-R>=ime#rpc#backspace()<C-M>"));
+    inoremap!("<Del>", format!("<C-R>=ime#rpc#delete()<C-M>"));
+    inoremap!("<Left>", format!("<C-R>=ime#rpc#move_cursor('left')<C-M>"));
+    inoremap!(
+      "<Right>",
+      format!("<C-R>=ime#rpc#move_cursor('right')<C-M>")
+    );
+    inoremap!("<Home>", format!("<C-R>=ime#rpc#move_cursor('start')<C-M>"));
+    inoremap!("<End>", format!("<C-R>=ime#rpc#move_cursor('end')<C-M>"));
     inoremap!(",", format!("<C-R>=ime#rpc#previous_page()<C-M>"));
     inoremap!(".", format!("<C-R>=ime#rpc#next_page()<C-M>"));
     for i in 1..(CANDIDATE_PER_PAGE + 1) {
       inoremap!(i, format!("<C-R>=ime#rpc#feed_number({})<C-M>", i));
     }
+    // tone keys work as their usual mapping where no tone is accepted
+    for ch in tone_keys {
+      let fallback = match ch {
+        ',' => "ime#rpc#previous_page()".to_string(),
+        '.' => "ime#rpc#next_page()".to_string(),
+        _ => match ch.to_digit(10) {
+          Some(i) if i >= 1 && i as usize <= CANDIDATE_PER_PAGE => {
+            format!("ime#rpc#feed_number({})", i)
+          }
+          _ => String::new(),
+        },
+      };
+      inoremap!(
+        ch,
+        format!(
+          "<C-R>=ime#rpc#feed_tone('{}', '{}')<C-M>",
+          ch.to_string().replace('\'', "''"),
+          fallback
+        )
+      );
+    }
 
     Ok(Value::from(true))
   }
 
-  async fn unregister_events(&self, neovim: Neovim<Stdout>) -> Result<Value, Value> {
-    let keycodes = self.engine.lock().await.keycodes();
+  async fn unregister_events(
+    &self,
+    args: Vec<Value>,
+    neovim: Neovim<Stdout>,
+  ) -> Result<Value, Value> {
+    if args.len() < 1 {
+      return Err(Value::from("expect at least 1 argument"));
+    }
+    let bufnr = args[0]
+      .as_i64()
+      .ok_or_else(|| Value::from("first parameter should be int"))?;
 
     let buf = neovim
       .get_current_buf()
       .await
       .map_err(|_| Value::from("failed to get current buffer"))?;
 
-    let mut mappings = self.mappings.lock().await;
-    for ch in mappings.clone() {
-      buf
-        .del_keymap("i", &ch)
-        .await
-        .map_err(|_| Value::from(format!("failed to unregister keymap: {}", ch)))?;
+    if let Some(mappings) = self.mappings.lock().await.remove(&bufnr) {
+      for ch in mappings {
+        buf
+          .del_keymap("i", &ch)
+          .await
+          .map_err(|_| Value::from(format!("failed to unregister keymap: {}", ch)))?;
+      }
     }
 
-    mappings.clear();
     Ok(Value::from(true))
   }
+
+  async fn switch_engine(&self, args: Vec<Value>, neovim: Neovim<Stdout>) -> Result<Value, Value> {
+    // args: [name, bufnr]
+    if args.len() < 2 {
+      return Err(Value::from("expect at least 2 arguments"));
+    }
+    let name = args[0]
+      .as_str()
+      .ok_or_else(|| Value::from("first parameter should be str"))?;
+    let bufnr = args[1]
+      .as_i64()
+      .ok_or_else(|| Value::from("second parameter should be int"))?;
+
+    if !self.engines.contains_key(name) {
+      return Err(Value::from(format!("no engine named: '{}'", name)));
+    }
+    self
+      .buffer_engine
+      .lock()
+      .await
+      .insert(bufnr, name.to_string());
+    info!("buffer {} switched to engine {}", bufnr, name);
+
+    // keymaps follow keycodes of the new engine
+    let registered = self.mappings.lock().await.contains_key(&bufnr);
+    if registered {
+      self
+        .unregister_events(make_args![bufnr], neovim.clone())
+        .await?;
+      self.register_events(make_args![bufnr], neovim).await?;
+    }
+
+    Ok(Value::from(name))
+  }
+
+  // called before Neovim exits, which may kill the process without dropping
+  // the engines
+  async fn flush(&self, _args: Vec<Value>, _neovim: Neovim<Stdout>) -> Result<Value, Value> {
+    self.flush_engines().await;
+
+    Ok(Value::from(true))
+  }
+
+  async fn list_engines(&self, args: Vec<Value>, _neovim: Neovim<Stdout>) -> Result<Value, Value> {
+    if args.len() < 1 {
+      return Err(Value::from("expect at least 1 argument"));
+    }
+    let bufnr = args[0]
+      .as_i64()
+      .ok_or_else(|| Value::from("first parameter should be int"))?;
+
+    let mut names: Vec<&String> = self.engines.keys().collect();
+    names.sort();
+
+    Ok(Value::from(vim_dict! {
+      "engines" => names.iter().map(|name| Value::from(name.as_str())).collect::<Vec<Value>>(),
+      "current" => self.engine_name(bufnr).await,
+    }))
+  }
+
+  fn phrase_args(args: &Vec<Value>) -> Result<(String, i64, Option<String>), Value> {
+    // args: [text, bufnr] or [text, bufnr, code]
+    if args.len() < 2 {
+      return Err(Value::from("expect at least 2 arguments"));
+    }
+    let text = args[0]
+      .as_str()
+      .ok_or_else(|| Value::from("first parameter should be str"))?;
+    let bufnr = args[1]
+      .as_i64()
+      .ok_or_else(|| Value::from("second parameter should be int"))?;
+    let code = match args.get(2) {
+      Some(code) => Some(
+        code
+          .as_str()
+          .ok_or_else(|| Value::from("third parameter should be str"))?
+          .to_string(),
+      ),
+      None => None,
+    };
+
+    Ok((text.to_string(), bufnr, code))
+  }
+
+  async fn add_phrase(&self, args: Vec<Value>, _neovim: Neovim<Stdout>) -> Result<Value, Value> {
+    let (text, bufnr, code) = PluginManager::phrase_args(&args)?;
+
+    let code = self
+      .engine(bufnr)
+      .await?
+      .lock()
+      .await
+      .add_phrase(&text, code.as_deref())
+      .map_err(Value::from)?;
+
+    Ok(Value::from(code))
+  }
+
+  async fn delete_phrase(&self, args: Vec<Value>, _neovim: Neovim<Stdout>) -> Result<Value, Value> {
+    let (text, bufnr, code) = PluginManager::phrase_args(&args)?;
+
+    self
+      .engine(bufnr)
+      .await?
+      .lock()
+      .await
+      .delete_phrase(&text, code.as_deref())
+      .map_err(Value::from)?;
+
+    Ok(Value::from(true))
+  }
+
+  fn reverse_lookup_args(args: &[Value]) -> Result<(String, i64, Option<String>), Value> {
+    // args: [text, bufnr] or [text, bufnr, engine]
+    if args.len() < 2 || args.len() > 3 {
+      return Err(Value::from("expect 2 or 3 arguments"));
+    }
+    let text = args[0]
+      .as_str()
+      .ok_or_else(|| Value::from("first parameter should be str"))?;
+    let bufnr = args[1]
+      .as_i64()
+      .ok_or_else(|| Value::from("second parameter should be int"))?;
+    let name = match args.get(2) {
+      Some(name) => Some(
+        name
+          .as_str()
+          .ok_or_else(|| Value::from("third parameter should be an engine name"))?
+          .to_string(),
+      ),
+      None => None,
+    };
+
+    Ok((text.to_string(), bufnr, name))
+  }
+
+  // code table engine showing codes for the engine of the buffer
+  async fn reverse_engine(&self, bufnr: i64) -> Option<Arc<Mutex<dyn IMEngine>>> {
+    let name = self.reverse_lookup.get(&self.engine_name(bufnr).await)?;
+
+    self.engines.get(name).cloned()
+  }
+
+  // shows codes of the reverse lookup engine next to candidates
+  async fn annotate(&self, bufnr: i64, candidates: &mut Vec<Candidate>) {
+    if let Some(engine) = self.reverse_engine(bufnr).await {
+      let engine = engine.lock().await;
+
+      for candidate in candidates.iter_mut().filter(|x| x.codes.is_empty()) {
+        if let Some(code) = engine.reverse_lookup(&candidate.text).first() {
+          candidate.codes = code.chars().collect();
+        }
+      }
+    }
+  }
+
+  async fn reverse_lookup(
+    &self,
+    args: Vec<Value>,
+    _neovim: Neovim<Stdout>,
+  ) -> Result<Value, Value> {
+    let (text, bufnr, name) = PluginManager::reverse_lookup_args(&args)?;
+
+    let engine = match name {
+      Some(name) => self
+        .engines
+        .get(&name)
+        .cloned()
+        .ok_or_else(|| Value::from(format!("no engine named: '{}'", name)))?,
+      None => match self.reverse_engine(bufnr).await {
+        Some(engine) => engine,
+        None => self.engine(bufnr).await?,
+      },
+    };
+    let codes = engine.lock().await.reverse_lookup(&text);
+
+    Ok(Value::from(
+      codes.into_iter().map(Value::from).collect::<Vec<Value>>(),
+    ))
+  }
 }